@@ -0,0 +1,238 @@
+use crate::query::{walk_all, Visibility};
+use crate::{Filter, Note, QueryOptions, Result, Transaction};
+use std::collections::HashMap;
+
+/// What [Ndb::aggregate](crate::Ndb::aggregate) groups notes by
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GroupBy {
+    Author,
+    Kind,
+    /// The values of this single letter tag, eg. `'t'` for hashtags. A note
+    /// is counted once for each distinct value it has.
+    Tag(char),
+    /// Buckets of this many seconds, starting at multiples of it
+    TimeBucket(u64),
+}
+
+/// The value a [Group] is for
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum GroupKey {
+    Author([u8; 32]),
+    Kind(u32),
+    /// A tag value that nostrdb stores packed as an id, like `e` and `p`
+    /// tags
+    TagId([u8; 32]),
+    Tag(String),
+    /// The start of the bucket, in unix seconds
+    TimeBucket(u64),
+}
+
+/// How many notes fall under one [GroupKey]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Group {
+    pub key: GroupKey,
+    pub count: u64,
+}
+
+impl GroupBy {
+    fn keys(&self, note: &Note) -> Vec<GroupKey> {
+        match *self {
+            GroupBy::Author => vec![GroupKey::Author(*note.pubkey())],
+            GroupBy::Kind => vec![GroupKey::Kind(note.kind())],
+            GroupBy::TimeBucket(secs) => {
+                let secs = secs.max(1);
+                vec![GroupKey::TimeBucket(note.created_at() / secs * secs)]
+            }
+            GroupBy::Tag(name) => {
+                let mut keys = vec![];
+                for tag in note.tags() {
+                    if tag.count() < 2 {
+                        continue;
+                    }
+
+                    let Some(tag_name) = tag.get_str(0) else {
+                        continue;
+                    };
+                    if tag_name.len() != 1 || !tag_name.starts_with(name) {
+                        continue;
+                    }
+
+                    let key = match tag.get_id(1) {
+                        Some(id) => GroupKey::TagId(*id),
+                        None => match tag.get_str(1) {
+                            Some(s) => GroupKey::Tag(s.to_owned()),
+                            None => continue,
+                        },
+                    };
+                    if !keys.contains(&key) {
+                        keys.push(key);
+                    }
+                }
+                keys
+            }
+        }
+    }
+}
+
+/// Count the notes matching any of `filters` in each group, walking the
+/// indices without collecting the notes. Replaced and deleted notes are
+/// left out, as in [crate::Ndb::count]. Time buckets come back oldest
+/// first, other groups most common first.
+pub(crate) fn aggregate(
+    txn: &Transaction,
+    filters: &[Filter],
+    group_by: GroupBy,
+) -> Result<Vec<Group>> {
+    let mut counts: HashMap<GroupKey, u64> = HashMap::new();
+    let mut visibility = Visibility::new(txn, QueryOptions::new());
+
+    walk_all(txn, filters, |note| {
+        if !visibility.keep(note) {
+            return;
+        }
+
+        for key in group_by.keys(note) {
+            *counts.entry(key).or_default() += 1;
+        }
+    })?;
+    visibility.finish()?;
+
+    let mut groups: Vec<Group> = counts
+        .into_iter()
+        .map(|(key, count)| Group { key, count })
+        .collect();
+
+    if let GroupBy::TimeBucket(_) = group_by {
+        groups.sort_by(|a, b| a.key.cmp(&b.key));
+    } else {
+        groups.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+    }
+
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::{test_util, Ndb, NoteBuilder};
+
+    #[test]
+    fn aggregate_works() {
+        let db = "target/testdbs/aggregate";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let alice = [50; 32];
+            let bob = [51; 32];
+
+            let note = |seckey: &[u8; 32], kind: u32, created_at: u64, hashtags: &[&str]| {
+                let mut builder = NoteBuilder::new()
+                    .kind(kind)
+                    .content("hello")
+                    .created_at(created_at);
+                for hashtag in hashtags {
+                    builder = builder.start_tag().tag_str("t").tag_str(hashtag);
+                }
+                builder.sign(seckey).build().expect("note")
+            };
+
+            let mut notes = vec![
+                note(&alice, 1, 3600, &["nostr", "nostr"]),
+                note(&alice, 1, 3700, &["nostr", "rust"]),
+                note(&alice, 7, 7300, &[]),
+                note(&bob, 1, 7400, &["nostr"]),
+                // replaced and deleted notes aren't counted
+                note(&bob, 3, 100, &[]),
+                note(&bob, 3, 200, &[]),
+                note(&bob, 1, 7500, &["nostr"]),
+            ];
+            let deletion = NoteBuilder::new()
+                .kind(5)
+                .content("")
+                .created_at(7600)
+                .start_tag()
+                .tag_str("e")
+                .tag_id(notes[6].id())
+                .sign(&bob)
+                .build()
+                .expect("deletion");
+            notes.push(deletion);
+            test_util::ingest_notes(&ndb, &notes);
+            let alice_pk = *notes[0].pubkey();
+            let bob_pk = *notes[3].pubkey();
+
+            let txn = Transaction::new(&ndb).expect("txn");
+
+            let hashtags = Filter::new().kinds([1]).build();
+            let groups = ndb
+                .aggregate(&txn, &[hashtags], GroupBy::Tag('t'))
+                .expect("aggregate");
+            assert_eq!(
+                groups,
+                vec![
+                    Group {
+                        key: GroupKey::Tag("nostr".to_string()),
+                        count: 3
+                    },
+                    Group {
+                        key: GroupKey::Tag("rust".to_string()),
+                        count: 1
+                    },
+                ]
+            );
+
+            let nostr = Filter::new().tags(["nostr"], 't').build();
+            let groups = ndb
+                .aggregate(&txn, &[nostr], GroupBy::Author)
+                .expect("aggregate");
+            assert_eq!(groups[0].key, GroupKey::Author(alice_pk));
+            assert_eq!(groups[0].count, 2);
+            assert_eq!(groups[1].key, GroupKey::Author(bob_pk));
+
+            let by_alice = Filter::new().authors([&alice_pk]).build();
+            let kinds = ndb
+                .aggregate(&txn, std::slice::from_ref(&by_alice), GroupBy::Kind)
+                .expect("aggregate");
+            assert_eq!(kinds[0].key, GroupKey::Kind(1));
+            assert_eq!(kinds[0].count, 2);
+            assert_eq!(kinds[1].key, GroupKey::Kind(7));
+
+            let everything = Filter::new().kinds([1, 7]).build();
+            let hours = ndb
+                .aggregate(&txn, &[everything, by_alice], GroupBy::TimeBucket(3600))
+                .expect("aggregate");
+            assert_eq!(
+                hours,
+                vec![
+                    Group {
+                        key: GroupKey::TimeBucket(3600),
+                        count: 2
+                    },
+                    Group {
+                        key: GroupKey::TimeBucket(7200),
+                        count: 2
+                    },
+                ]
+            );
+
+            // the same notes as count
+            let by_bob = Filter::new().authors([&bob_pk]).build();
+            let kind_1 = Filter::new().kinds([1]).build();
+            for (filter, expected) in [(by_bob, 3), (kind_1, 3)] {
+                let filters = std::slice::from_ref(&filter);
+                let total: u64 = ndb
+                    .aggregate(&txn, filters, GroupBy::Kind)
+                    .expect("aggregate")
+                    .iter()
+                    .map(|group| group.count)
+                    .sum();
+                assert_eq!(total, expected);
+                assert_eq!(total, ndb.count(&txn, filters).expect("count"));
+            }
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
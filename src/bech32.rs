@@ -0,0 +1,378 @@
+//! NIP-19 bech32 entities. Decoding goes through the same `nostr_bech32`
+//! parser nostrdb uses for mentions in note content, and encoding uses its
+//! bech32 routines, which aren't exposed in its headers.
+
+use crate::{bindings, Bech32Type, Error, Mention, NoteAddress, Result};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+
+const BECH32_ENCODING_BECH32: c_int = 1;
+
+const TLV_SPECIAL: u8 = 0;
+const TLV_RELAY: u8 = 1;
+const TLV_AUTHOR: u8 = 2;
+const TLV_KIND: u8 = 3;
+
+extern "C" {
+    fn bech32_encode(
+        output: *mut c_char,
+        hrp: *const c_char,
+        data: *const u8,
+        data_len: usize,
+        max_input_len: usize,
+        enc: c_int,
+    ) -> c_int;
+
+    fn bech32_decode(
+        hrp: *mut c_char,
+        data: *mut u8,
+        data_len: *mut usize,
+        input: *const c_char,
+        max_input_len: usize,
+    ) -> c_int;
+
+    fn parse_nostr_bech32(
+        buf: *mut u8,
+        buflen: c_int,
+        bech32_str: *const c_char,
+        bech32_len: usize,
+        obj: *mut bindings::nostr_bech32,
+    ) -> c_int;
+}
+
+/// A decoded NIP-19 entity such as an `npub` or `nevent`
+pub struct Bech32 {
+    bech32: bindings::nostr_bech32,
+    kind: Option<u32>,
+
+    // the parsed fields point into this
+    #[allow(dead_code)]
+    buf: Vec<u8>,
+}
+
+impl std::fmt::Debug for Bech32 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Bech32")
+            .field("type", &self.bech32_type())
+            .finish()
+    }
+}
+
+impl Bech32 {
+    /// Decode a NIP-19 string. A leading `nostr:` is allowed.
+    pub fn decode(input: &str) -> Result<Bech32> {
+        let input = input.strip_prefix("nostr:").unwrap_or(input);
+        let mut buf = vec![0u8; input.len()];
+        let mut bech32 = std::mem::MaybeUninit::<bindings::nostr_bech32>::zeroed();
+
+        let ok = unsafe {
+            parse_nostr_bech32(
+                buf.as_mut_ptr(),
+                buf.len() as c_int,
+                input.as_ptr() as *const c_char,
+                input.len(),
+                bech32.as_mut_ptr(),
+            )
+        };
+
+        // the parser stops at the first non-alphanumeric character, so make
+        // sure it was handed a single entity
+        if ok == 0 || !input.bytes().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(Error::DecodeError);
+        }
+
+        let bech32 = unsafe { bech32.assume_init() };
+
+        // nostrdb's parser skips the kind, so pull it out ourselves
+        let kind = match Bech32Type::from_ctype(bech32.type_) {
+            Bech32Type::Event | Bech32Type::Addr => tlv_kind(input),
+            _ => None,
+        };
+
+        Ok(Bech32 { bech32, kind, buf })
+    }
+
+    /// The kind of an `nevent` or `naddr`, if it was included
+    pub fn kind(&self) -> Option<u32> {
+        self.kind
+    }
+
+    pub fn bech32_type(&self) -> Bech32Type {
+        Bech32Type::from_ctype(self.bech32.type_)
+    }
+
+    /// The decoded fields, in the same form as mentions in note content
+    pub fn as_mention(&self) -> Mention<'_> {
+        Mention::new(&self.bech32)
+    }
+
+    pub fn encode_npub(pubkey: &[u8; 32]) -> String {
+        encode("npub", &bytes_to_words(pubkey))
+    }
+
+    pub fn encode_nsec(seckey: &[u8; 32]) -> String {
+        encode("nsec", &bytes_to_words(seckey))
+    }
+
+    pub fn encode_note(id: &[u8; 32]) -> String {
+        encode("note", &bytes_to_words(id))
+    }
+
+    /// Encode an `nevent`. Fails with [`Error::BufferOverflow`] if a relay
+    /// is longer than 255 bytes.
+    pub fn encode_nevent(
+        id: &[u8; 32],
+        relays: &[&str],
+        author: Option<&[u8; 32]>,
+        kind: Option<u32>,
+    ) -> Result<String> {
+        let mut tlvs = vec![];
+        push_tlv(&mut tlvs, TLV_SPECIAL, id)?;
+        push_relays(&mut tlvs, relays)?;
+        if let Some(author) = author {
+            push_tlv(&mut tlvs, TLV_AUTHOR, author)?;
+        }
+        if let Some(kind) = kind {
+            push_tlv(&mut tlvs, TLV_KIND, &kind.to_be_bytes())?;
+        }
+        Ok(encode("nevent", &bytes_to_words(&tlvs)))
+    }
+
+    /// Encode an `nprofile`. Fails with [`Error::BufferOverflow`] if a
+    /// relay is longer than 255 bytes.
+    pub fn encode_nprofile(pubkey: &[u8; 32], relays: &[&str]) -> Result<String> {
+        let mut tlvs = vec![];
+        push_tlv(&mut tlvs, TLV_SPECIAL, pubkey)?;
+        push_relays(&mut tlvs, relays)?;
+        Ok(encode("nprofile", &bytes_to_words(&tlvs)))
+    }
+
+    /// Encode an `naddr`. Fails with [`Error::BufferOverflow`] if the `d`
+    /// identifier or a relay is longer than 255 bytes.
+    pub fn encode_naddr(address: &NoteAddress, relays: &[&str]) -> Result<String> {
+        let mut tlvs = vec![];
+        push_tlv(&mut tlvs, TLV_SPECIAL, address.identifier.as_bytes())?;
+        push_relays(&mut tlvs, relays)?;
+        push_tlv(&mut tlvs, TLV_AUTHOR, &address.pubkey)?;
+        push_tlv(&mut tlvs, TLV_KIND, &address.kind.to_be_bytes())?;
+        Ok(encode("naddr", &bytes_to_words(&tlvs)))
+    }
+}
+
+fn tlv_kind(input: &str) -> Option<u32> {
+    let (_hrp, words) = decode(input)?;
+    let tlvs = words_to_bytes(&words);
+
+    let mut i = 0;
+    while i + 2 <= tlvs.len() {
+        let typ = tlvs[i];
+        let len = tlvs[i + 1] as usize;
+        let value = tlvs.get(i + 2..i + 2 + len)?;
+        if typ == TLV_KIND {
+            return Some(u32::from_be_bytes(value.try_into().ok()?));
+        }
+        i += 2 + len;
+    }
+
+    None
+}
+
+fn push_tlv(tlvs: &mut Vec<u8>, typ: u8, value: &[u8]) -> Result<()> {
+    let len: u8 = value.len().try_into().map_err(|_| Error::BufferOverflow)?;
+    tlvs.push(typ);
+    tlvs.push(len);
+    tlvs.extend_from_slice(value);
+    Ok(())
+}
+
+fn push_relays(tlvs: &mut Vec<u8>, relays: &[&str]) -> Result<()> {
+    for relay in relays {
+        push_tlv(tlvs, TLV_RELAY, relay.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Encode 5-bit words as a bech32 string
+pub(crate) fn encode(hrp: &str, words: &[u8]) -> String {
+    let c_hrp = CString::new(hrp).expect("hrp");
+    let mut output = vec![0u8; hrp.len() + words.len() + 8];
+
+    let ok = unsafe {
+        bech32_encode(
+            output.as_mut_ptr() as *mut c_char,
+            c_hrp.as_ptr(),
+            words.as_ptr(),
+            words.len(),
+            output.len(),
+            BECH32_ENCODING_BECH32,
+        )
+    };
+    assert_eq!(ok, 1, "bech32 encode failed");
+
+    output.truncate(hrp.len() + words.len() + 7);
+    String::from_utf8(output).expect("bech32 is ascii")
+}
+
+/// Decode a bech32 string into its human readable part and 5-bit words.
+/// The human readable part is limited to 10 characters, same as nostrdb.
+pub(crate) fn decode(input: &str) -> Option<(String, Vec<u8>)> {
+    let c_input = CString::new(input).ok()?;
+    let mut hrp = vec![0u8; input.len()];
+    let mut data = vec![0u8; input.len()];
+    let mut data_len: usize = 0;
+
+    let enc = unsafe {
+        bech32_decode(
+            hrp.as_mut_ptr() as *mut c_char,
+            data.as_mut_ptr(),
+            &mut data_len,
+            c_input.as_ptr(),
+            input.len(),
+        )
+    };
+    if enc != BECH32_ENCODING_BECH32 {
+        return None;
+    }
+
+    let hrp_len = hrp.iter().position(|&c| c == 0)?;
+    hrp.truncate(hrp_len);
+    data.truncate(data_len);
+
+    Some((String::from_utf8(hrp).ok()?, data))
+}
+
+/// Regroup bytes into 5-bit words, padding the last one with zeros
+pub(crate) fn bytes_to_words(bytes: &[u8]) -> Vec<u8> {
+    let mut words = Vec::with_capacity(bytes.len() * 8 / 5 + 1);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+
+    for byte in bytes {
+        acc = (acc << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            words.push(((acc >> bits) & 31) as u8);
+        }
+    }
+
+    if bits > 0 {
+        words.push(((acc << (5 - bits)) & 31) as u8);
+    }
+
+    words
+}
+
+/// Regroup 5-bit words into bytes, dropping the padding bits at the end
+pub(crate) fn words_to_bytes(words: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(words.len() * 5 / 8);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+
+    for word in words {
+        acc = (acc << 5) | (*word as u32 & 31);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    // from https://github.com/nostr-protocol/nips/blob/master/19.md#examples
+    const NPUB: &str = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+    const NPROFILE: &str = "nprofile1qqsrhuxx8l9ex335q7he0f09aej04zpazpl0ne2cgukyawd24mayt8gpp4mhxue69uhhytnc9e3k7mgpz4mhxue69uhkg6nzv9ejuumpv34kytnrdaksjlyr9p";
+
+    fn pubkey() -> [u8; 32] {
+        hex::decode("7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e")
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn bech32_npub_roundtrip() {
+        let pubkey = pubkey();
+        assert_eq!(Bech32::encode_npub(&pubkey), NPUB);
+
+        let decoded = Bech32::decode(&format!("nostr:{NPUB}")).expect("decode");
+        assert_eq!(decoded.bech32_type(), Bech32Type::Pubkey);
+        match decoded.as_mention() {
+            Mention::Pubkey(npub) => assert_eq!(npub.pubkey(), &pubkey),
+            _ => panic!("expected npub"),
+        }
+
+        assert!(Bech32::decode("npub1garbage").is_err());
+        assert!(Bech32::decode(&format!("{NPUB} trailing")).is_err());
+    }
+
+    #[test]
+    fn bech32_nprofile_roundtrip() {
+        let pubkey: [u8; 32] =
+            hex::decode("3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let relays = ["wss://r.x.com", "wss://djbas.sadkb.com"];
+
+        let encoded = Bech32::encode_nprofile(&pubkey, &relays).expect("encode");
+        assert_eq!(encoded, NPROFILE);
+
+        let decoded = Bech32::decode(NPROFILE).expect("decode");
+        match decoded.as_mention() {
+            Mention::Profile(p) => {
+                assert_eq!(p.pubkey(), &pubkey);
+                assert_eq!(p.relays_iter().collect::<Vec<_>>(), relays);
+            }
+            _ => panic!("expected nprofile"),
+        }
+    }
+
+    #[test]
+    fn bech32_nevent_and_naddr_roundtrip() {
+        let id = [0x42; 32];
+        let author = pubkey();
+
+        let nevent =
+            Bech32::encode_nevent(&id, &["wss://nos.lol"], Some(&author), Some(1)).expect("encode");
+        let decoded = Bech32::decode(&nevent).expect("decode");
+        assert_eq!(decoded.kind(), Some(1));
+        match decoded.as_mention() {
+            Mention::Event(ev) => {
+                assert_eq!(ev.id(), &id);
+                assert_eq!(ev.pubkey(), Some(&author));
+                assert_eq!(ev.relays_iter().collect::<Vec<_>>(), vec!["wss://nos.lol"]);
+            }
+            _ => panic!("expected nevent"),
+        }
+
+        let address = NoteAddress::new(30023, &author, "my-article");
+        let naddr = Bech32::encode_naddr(&address, &[]).expect("encode");
+        let decoded = Bech32::decode(&naddr).expect("decode");
+        assert_eq!(decoded.bech32_type(), Bech32Type::Addr);
+        assert_eq!(decoded.kind(), Some(30023));
+        match decoded.as_mention() {
+            Mention::Addr(addr) => {
+                assert_eq!(addr.identifier(), "my-article");
+                assert_eq!(addr.pubkey(), Some(&author));
+            }
+            _ => panic!("expected naddr"),
+        }
+
+        let note = Bech32::encode_note(&id);
+        assert!(note.starts_with("note1"));
+        match Bech32::decode(&note).expect("decode").as_mention() {
+            Mention::Note(n) => assert_eq!(n.id(), &id),
+            _ => panic!("expected note"),
+        }
+
+        let long_relay = format!("wss://{}", "a".repeat(255));
+        assert!(Bech32::encode_nprofile(&author, &[&long_relay]).is_err());
+    }
+}
@@ -1,4 +1,5 @@
-use crate::{bindings, Note, Transaction};
+use crate::{bech32, bindings, Bech32, Note, ProfileRecord, Transaction};
+use std::ffi::CStr;
 
 #[derive(Debug)]
 pub struct Blocks<'a> {
@@ -40,6 +41,21 @@ pub enum Bech32Type {
     Secret,
 }
 
+/// A lightning invoice found in note content, decoded by nostrdb's bolt11
+/// parser when the note was ingested
+#[derive(Clone, Copy, Debug)]
+pub struct Invoice<'a> {
+    block: &'a bindings::ndb_invoice_block,
+}
+
+/// What an invoice is for. Invoices either include a short description, or
+/// commit to a longer one with its sha256 hash.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InvoiceDescription<'a> {
+    Text(&'a str),
+    Hash(&'a [u8; 32]),
+}
+
 pub enum Mention<'a> {
     Pubkey(&'a bindings::bech32_npub),
     Event(&'a bindings::bech32_nevent),
@@ -50,6 +66,12 @@ pub enum Mention<'a> {
     Addr(&'a bindings::bech32_naddr),
 }
 
+/// What a [`Mention`] points at, from [`crate::Ndb::resolve_mention`]
+pub enum ResolvedMention<'a> {
+    Note(Note<'a>),
+    Profile(ProfileRecord<'a>),
+}
+
 impl bindings::ndb_str_block {
     pub fn as_str(&self) -> &str {
         unsafe {
@@ -67,6 +89,12 @@ impl bindings::bech32_nrelay {
     }
 }
 
+impl bindings::bech32_nsec {
+    pub fn seckey(&self) -> &[u8; 32] {
+        unsafe { &*(self.nsec as *const [u8; 32]) }
+    }
+}
+
 impl bindings::bech32_nprofile {
     pub fn pubkey(&self) -> &[u8; 32] {
         unsafe { &*(self.pubkey as *const [u8; 32]) }
@@ -113,6 +141,20 @@ impl bindings::bech32_nevent {
 }
 
 impl bindings::bech32_naddr {
+    /// The `d` tag of the addressed event
+    pub fn identifier(&self) -> &str {
+        self.identifier.as_str()
+    }
+
+    pub fn pubkey(&self) -> Option<&[u8; 32]> {
+        unsafe {
+            if self.pubkey.is_null() {
+                return None;
+            }
+            Some(&*(self.pubkey as *const [u8; 32]))
+        }
+    }
+
     pub fn relays_iter(&self) -> impl Iterator<Item = &str> {
         self.relays.relays[0..(self.relays.num_relays as usize)]
             .iter()
@@ -120,6 +162,66 @@ impl bindings::bech32_naddr {
     }
 }
 
+impl<'a> Invoice<'a> {
+    /// The bolt11 invoice string
+    pub fn as_str(&self) -> &'a str {
+        self.block.invstr.as_str()
+    }
+
+    /// The amount in millisatoshis. `None` for invoices where the payer
+    /// picks the amount.
+    pub fn amount_msats(&self) -> Option<u64> {
+        Some(self.block.invoice.amount).filter(|amount| *amount != 0)
+    }
+
+    pub fn description(&self) -> Option<InvoiceDescription<'a>> {
+        let invoice = &self.block.invoice;
+        if !invoice.description_hash.is_null() {
+            let hash = unsafe { &*(invoice.description_hash as *const [u8; 32]) };
+            Some(InvoiceDescription::Hash(hash))
+        } else if !invoice.description.is_null() {
+            let text = unsafe { CStr::from_ptr(invoice.description) };
+            text.to_str().ok().map(InvoiceDescription::Text)
+        } else {
+            None
+        }
+    }
+
+    /// Unix timestamp of when the invoice was created
+    pub fn timestamp(&self) -> u64 {
+        self.block.invoice.timestamp
+    }
+
+    /// Seconds after [`Invoice::timestamp`] that the invoice expires
+    pub fn expiry(&self) -> u64 {
+        self.block.invoice.expiry
+    }
+
+    /// The payment hash. nostrdb doesn't store this when it parses content,
+    /// so it is decoded from the invoice string on each call.
+    pub fn payment_hash(&self) -> Option<[u8; 32]> {
+        let (_hrp, words) = bech32::decode(self.as_str())?;
+
+        // 35-bit timestamp, then tagged fields, then a 520-bit signature
+        let fields = words.get(7..words.len().checked_sub(104)?)?;
+        let mut i = 0;
+        while i + 3 <= fields.len() {
+            let typ = fields[i];
+            let len = (fields[i + 1] as usize) << 5 | fields[i + 2] as usize;
+            let data = fields.get(i + 3..i + 3 + len)?;
+
+            // 'p' fields that aren't 52 words long must be skipped
+            if typ == 1 && len == 52 {
+                return bech32::words_to_bytes(data).try_into().ok();
+            }
+
+            i += 3 + len;
+        }
+
+        None
+    }
+}
+
 impl<'a> Mention<'a> {
     pub fn new(bech32: &'a bindings::nostr_bech32) -> Self {
         unsafe {
@@ -183,6 +285,16 @@ impl<'a> Block<'a> {
         Some(Mention::new(self.c_bech32()))
     }
 
+    /// Decode a mention again from its string. nostrdb doesn't keep every
+    /// field when it parses content, such as the kind of an `naddr`, so use
+    /// this when you need [`Bech32::kind`].
+    pub fn as_bech32(&self) -> Option<Bech32> {
+        if self.blocktype() != BlockType::MentionBech32 {
+            return None;
+        }
+        Bech32::decode(self.as_str()).ok()
+    }
+
     pub fn as_str(&self) -> &'a str {
         unsafe {
             let str_block = bindings::ndb_block_str(self.as_ptr());
@@ -194,6 +306,14 @@ impl<'a> Block<'a> {
         }
     }
 
+    pub fn as_invoice(&self) -> Option<Invoice<'a>> {
+        if self.blocktype() != BlockType::Invoice {
+            return None;
+        }
+        let block = unsafe { &(*self.as_ptr()).block.invoice };
+        Some(Invoice { block })
+    }
+
     fn c_bech32(&self) -> &'a bindings::nostr_bech32 {
         unsafe { &(*self.as_ptr()).block.mention_bech32.bech32 }
     }
@@ -452,4 +572,44 @@ mod tests {
 
         test_util::cleanup_db(&db);
     }
+
+    #[test]
+    fn invoice_blocks_work() {
+        let db = "target/testdbs/invoice_blocks";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let invoice = test_util::bolt11_invoice(21_000, &[1; 32], &[2; 32]);
+            let note = crate::NoteBuilder::new()
+                .kind(1)
+                .content(&format!("pay me {invoice}"))
+                .sign(&[3; 32])
+                .build()
+                .expect("note");
+            test_util::ingest_notes(&ndb, std::slice::from_ref(&note));
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let note = ndb.get_note_by_id(&txn, note.id()).expect("note");
+            let blocks = ndb
+                .get_blocks_by_key(&txn, note.key().unwrap())
+                .expect("blocks");
+
+            let invoices: Vec<Invoice> =
+                blocks.iter(&note).filter_map(|b| b.as_invoice()).collect();
+            assert_eq!(invoices.len(), 1);
+
+            let parsed = invoices[0];
+            assert_eq!(parsed.as_str(), invoice);
+            assert_eq!(parsed.amount_msats(), Some(21_000));
+            assert_eq!(
+                parsed.description(),
+                Some(InvoiceDescription::Hash(&[2; 32]))
+            );
+            assert_eq!(parsed.timestamp(), 1700000000);
+            assert_eq!(parsed.payment_hash(), Some([1; 32]));
+        }
+
+        test_util::cleanup_db(db);
+    }
 }
@@ -1,8 +1,11 @@
 use crate::bindings;
+use std::time::Duration;
 
 #[derive(Copy, Clone)]
 pub struct Config {
     pub config: bindings::ndb_config,
+    pub(crate) trending_window: Option<Duration>,
+    pub(crate) follower_index: bool,
 }
 
 impl Default for Config {
@@ -28,7 +31,11 @@ impl Config {
             bindings::ndb_default_config(&mut config);
         }
 
-        Config { config }
+        Config {
+            config,
+            trending_window: None,
+            follower_index: false,
+        }
     }
 
     //
@@ -71,6 +78,23 @@ impl Config {
         self
     }
 
+    /// Keep an index of the hashtags used in notes, articles and media
+    /// posts within this long ago, for [crate::Ndb::trending_hashtags]. It
+    /// is kept in memory, and rebuilt from the database when it is opened.
+    pub fn set_trending_window(mut self, window: Duration) -> Self {
+        self.trending_window = Some(window);
+        self
+    }
+
+    /// Keep an index of who follows whom by their newest contact lists,
+    /// for [crate::Ndb::followers]. Like the trending index, it is kept in
+    /// memory and rebuilt from every contact list in the database when it
+    /// is opened.
+    pub fn set_follower_index(mut self, enabled: bool) -> Self {
+        self.follower_index = enabled;
+        self
+    }
+
     pub fn set_ingester_threads(mut self, threads: i32) -> Self {
         self.config.ingester_threads = threads;
         self
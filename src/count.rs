@@ -0,0 +1,185 @@
+//! NIP-45 counts. Each filter runs as a query with a custom filter element
+//! that counts the notes that get that far and then rejects them, so
+//! nostrdb walks its indices without collecting any results.
+
+use crate::query::{walk_all, Visibility};
+use crate::{Filter, Note, QueryOptions, Result, Transaction};
+
+/// The answer to a count, as in a NIP-45 `COUNT` response
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Count {
+    pub count: u64,
+    /// True when the count went past [CountOptions::approximate_above], so
+    /// some of it includes replaced or deleted notes
+    pub approximate: bool,
+}
+
+/// How [Ndb::count_with] counts
+///
+/// [Ndb::count_with]: crate::Ndb::count_with
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct CountOptions {
+    approximate_above: Option<u64>,
+}
+
+impl CountOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Once this many notes have been counted, stop checking each note for
+    /// newer versions, deletions and matches by earlier filters, and just
+    /// count the index entries that match. Very large counts get much
+    /// cheaper, but are only approximate.
+    pub fn approximate_above(mut self, count: u64) -> Self {
+        self.approximate_above = Some(count);
+        self
+    }
+}
+
+struct Counter<'a> {
+    visibility: Visibility<'a>,
+    approximate_above: Option<u64>,
+    count: u64,
+    approximate: bool,
+}
+
+impl Counter<'_> {
+    fn visit(&mut self, note: &Note) {
+        if self
+            .approximate_above
+            .is_some_and(|limit| self.count >= limit)
+        {
+            self.count += 1;
+            self.approximate = true;
+            return;
+        }
+
+        // replaceable and addressable notes are only counted once per
+        // address, if the newest version matches
+        if self.visibility.keep(note) {
+            self.count += 1;
+        }
+    }
+}
+
+pub(crate) fn count(txn: &Transaction, filters: &[Filter], options: CountOptions) -> Result<Count> {
+    let mut counter = Counter {
+        visibility: Visibility::new(txn, QueryOptions::new()),
+        approximate_above: options.approximate_above,
+        count: 0,
+        approximate: false,
+    };
+
+    walk_all(txn, filters, |note| counter.visit(note))?;
+
+    let Counter {
+        visibility,
+        count,
+        approximate,
+        ..
+    } = counter;
+    visibility.finish()?;
+
+    Ok(Count { count, approximate })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::{test_util, Ndb, NoteBuilder};
+
+    #[test]
+    fn count_works() {
+        let db = "target/testdbs/count";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let target = NoteBuilder::new()
+                .kind(1)
+                .content("popular")
+                .created_at(1)
+                .sign(&[30; 32])
+                .build()
+                .expect("note");
+
+            let mut notes = vec![target.clone()];
+            for i in 0..20u8 {
+                let seckey = [40 + i; 32];
+                // a reply from everyone
+                notes.push(
+                    NoteBuilder::new()
+                        .kind(1)
+                        .content("reply")
+                        .created_at(10)
+                        .start_tag()
+                        .tag_str("e")
+                        .tag_id(target.id())
+                        .sign(&seckey)
+                        .build()
+                        .expect("reply"),
+                );
+                // everyone follows the target, and then half of them change
+                // their mind
+                for (created_at, follows) in [(10, true), (20, i % 2 == 0)] {
+                    let mut builder = NoteBuilder::new()
+                        .kind(3)
+                        .content("")
+                        .created_at(created_at);
+                    if follows {
+                        builder = builder.start_tag().tag_str("p").tag_id(target.pubkey());
+                    }
+                    notes.push(builder.sign(&seckey).build().expect("contacts"));
+                }
+            }
+
+            // the first reply is deleted
+            let deletion = NoteBuilder::new()
+                .kind(5)
+                .content("")
+                .created_at(30)
+                .start_tag()
+                .tag_str("e")
+                .tag_id(notes[1].id())
+                .sign(&[40; 32])
+                .build()
+                .expect("deletion");
+            notes.push(deletion);
+            test_util::ingest_notes(&ndb, &notes);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let replies = Filter::new().kinds([1]).event(target.id()).build();
+            let followers = Filter::new()
+                .kinds([3])
+                .pubkey([target.pubkey()])
+                .limit(1)
+                .build();
+
+            let count = |filters: &[Filter]| ndb.count(&txn, filters).expect("count");
+            assert_eq!(count(std::slice::from_ref(&replies)), 19);
+            assert_eq!(count(std::slice::from_ref(&followers)), 10);
+            assert_eq!(count(&[replies.clone(), replies.clone()]), 19);
+
+            let options = CountOptions::new().approximate_above(5);
+            let approx = ndb.count_with(&txn, &[replies], options).expect("count");
+            assert!(approx.approximate);
+            // the deleted reply is only left out if it was checked
+            assert!(approx.count == 19 || approx.count == 20);
+
+            let exact = ndb
+                .count_with(&txn, &[followers], CountOptions::new())
+                .expect("count");
+            assert_eq!(
+                exact,
+                Count {
+                    count: 10,
+                    approximate: false
+                }
+            );
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -24,6 +24,9 @@ pub enum Error {
     #[error("Subscription failed")]
     SubscriptionError,
 
+    #[error("Backup failed")]
+    BackupFailed,
+
     #[error("Buffer overflow")]
     BufferOverflow,
 
@@ -35,6 +38,12 @@ pub enum Error {
 
     #[error("IO error: {0}")]
     IO(#[from] std::io::Error),
+
+    #[error("NIP-44 error: {0}")]
+    Nip44(#[from] Nip44Error),
+
+    #[error("NIP-04 error: {0}")]
+    Nip04(#[from] Nip04Error),
 }
 
 /// Filter-specific error type
@@ -45,6 +54,12 @@ pub enum FilterError {
 
     #[error("Field already started")]
     FieldAlreadyStarted,
+
+    #[error("Too many fields")]
+    TooManyFields,
+
+    #[error("Unsupported filter element")]
+    UnsupportedElement,
 }
 
 impl FilterError {
@@ -55,4 +70,84 @@ impl FilterError {
     pub fn already_started() -> Error {
         Error::Filter(FilterError::FieldAlreadyStarted)
     }
+
+    pub fn too_many_fields() -> Error {
+        Error::Filter(FilterError::TooManyFields)
+    }
+
+    pub fn unsupported_element() -> Error {
+        Error::Filter(FilterError::UnsupportedElement)
+    }
+}
+
+/// NIP-44 encryption and decryption errors. Most map to nostrdb's
+/// `NIP44_ERR_*` codes.
+#[derive(Debug, Error, Clone, Copy, Eq, PartialEq)]
+pub enum Nip44Error {
+    #[error("Unsupported encoding")]
+    UnsupportedEncoding,
+
+    #[error("Invalid payload")]
+    InvalidPayload,
+
+    #[error("Error during base64 decoding")]
+    Base64Decode,
+
+    #[error("Secret key verify failed")]
+    SeckeyVerifyFailed,
+
+    #[error("Public key parse failed")]
+    PubkeyParseFailed,
+
+    #[error("ECDH failed")]
+    EcdhFailed,
+
+    #[error("Fill random failed")]
+    FillRandomFailed,
+
+    #[error("Invalid MAC")]
+    InvalidMac,
+
+    #[error("Invalid padding")]
+    InvalidPadding,
+
+    #[error("Buffer too small")]
+    BufferTooSmall,
+
+    /// Plaintexts must be between 1 and [nip44::MAX_PLAINTEXT_LEN](crate::nip44::MAX_PLAINTEXT_LEN) bytes
+    #[error("Invalid plaintext length")]
+    InvalidPlaintextLength,
+
+    #[error("Decrypted message is not valid UTF-8")]
+    InvalidUtf8,
+
+    #[error("Unknown NIP-44 error code {0}")]
+    Unknown(i64),
+}
+
+/// Legacy NIP-04 DM encryption and decryption errors
+#[derive(Debug, Error, Clone, Copy, Eq, PartialEq)]
+pub enum Nip04Error {
+    #[error("Not a kind 4 direct message")]
+    NotADirectMessage,
+
+    /// Our own DMs are decrypted with the pubkey in their `p` tag
+    #[error("Missing recipient")]
+    MissingRecipient,
+
+    #[error("Invalid payload")]
+    InvalidPayload,
+
+    #[error("Error during base64 decoding")]
+    Base64Decode,
+
+    #[error("ECDH failed")]
+    EcdhFailed,
+
+    /// This is also what decrypting with the wrong key usually looks like
+    #[error("Invalid padding")]
+    InvalidPadding,
+
+    #[error("Decrypted message is not valid UTF-8")]
+    InvalidUtf8,
 }
@@ -0,0 +1,313 @@
+use crate::query::query_raw;
+use crate::{Filter, Ndb, Note, NoteKey, Result, Transaction};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// How many notes to query at a time while exporting
+const EXPORT_BATCH_SIZE: u64 = 512;
+
+/// What [Ndb::export] writes besides the notes themselves
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ExportOptions {
+    relays: bool,
+    profiles: bool,
+}
+
+impl ExportOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write each note as `{"event":<note>,"relays":[...]}`, listing the
+    /// relays it was seen on, instead of as a bare note
+    pub fn relays(mut self, include: bool) -> Self {
+        self.relays = include;
+        self
+    }
+
+    /// Also write the newest profile (kind 0) of every author whose notes
+    /// are exported, so names can be shown wherever the notes are imported
+    pub fn profiles(mut self, include: bool) -> Self {
+        self.profiles = include;
+        self
+    }
+}
+
+struct Exporter<'a, W> {
+    ndb: &'a Ndb,
+    txn: &'a Transaction,
+    writer: W,
+    options: ExportOptions,
+    /// Only needed to skip notes matched by more than one filter
+    seen: Option<HashSet<[u8; 32]>>,
+    authors: HashSet<[u8; 32]>,
+    profiles: HashSet<[u8; 32]>,
+    written: usize,
+}
+
+pub(crate) fn export<W: Write>(
+    ndb: &Ndb,
+    txn: &Transaction,
+    filters: &[Filter],
+    writer: W,
+    options: ExportOptions,
+) -> Result<usize> {
+    let mut exporter = Exporter {
+        ndb,
+        txn,
+        writer,
+        options,
+        seen: (filters.len() > 1).then(HashSet::new),
+        authors: HashSet::new(),
+        profiles: HashSet::new(),
+        written: 0,
+    };
+
+    for filter in filters {
+        exporter.export_filter(filter)?;
+    }
+
+    exporter.writer.flush()?;
+    Ok(exporter.written)
+}
+
+impl<'a, W: Write> Exporter<'a, W> {
+    /// Page through everything matching `filter`, newest first, moving
+    /// `until` back past each page
+    fn export_filter(&mut self, filter: &Filter) -> Result<()> {
+        let mut remaining = filter.limit();
+        let mut page_size = EXPORT_BATCH_SIZE;
+        // the oldest timestamp written so far, and the notes written at it.
+        // pages can split notes that share a timestamp, so the next page
+        // starts at that timestamp again and skips those notes
+        let mut oldest: Option<u64> = None;
+        let mut boundary: HashSet<[u8; 32]> = HashSet::new();
+
+        loop {
+            let batch_size = remaining.map_or(page_size, |r| r.min(page_size));
+            if batch_size == 0 {
+                return Ok(());
+            }
+
+            let mut page = filter.clone().limit_mut(batch_size);
+            if let Some(oldest) = oldest {
+                // until is exclusive when scanning the indices
+                page = page.until_mut(oldest + 1);
+            }
+
+            let results = query_raw(self.txn, &[page], batch_size as i32)?;
+            let Some(page_oldest) = results.last().map(|res| res.note.created_at()) else {
+                return Ok(());
+            };
+
+            let mut next_boundary = if oldest == Some(page_oldest) {
+                boundary.clone()
+            } else {
+                HashSet::new()
+            };
+
+            let mut new_notes = 0;
+            for res in &results {
+                let created_at = res.note.created_at();
+                if oldest.is_some_and(|oldest| created_at > oldest)
+                    || (oldest == Some(created_at) && boundary.contains(res.note.id()))
+                {
+                    continue;
+                }
+
+                if created_at == page_oldest {
+                    next_boundary.insert(*res.note.id());
+                }
+
+                if self.write_note(&res.note)? {
+                    new_notes += 1;
+                }
+            }
+
+            boundary = next_boundary;
+            if let Some(r) = remaining.as_mut() {
+                *r = r.saturating_sub(new_notes);
+            }
+
+            if (results.len() as u64) < batch_size {
+                return Ok(());
+            }
+
+            if new_notes == 0 && oldest == Some(page_oldest) {
+                // a whole page of notes at a timestamp we already started
+                // on. grow the page until we get past them
+                page_size *= 2;
+            } else {
+                oldest = Some(page_oldest);
+            }
+        }
+    }
+
+    /// Returns false if the note was already written
+    fn write_note(&mut self, note: &Note<'a>) -> Result<bool> {
+        let id = note.id();
+        if self.profiles.contains(id) {
+            return Ok(false);
+        }
+        if let Some(seen) = self.seen.as_mut() {
+            if !seen.insert(*id) {
+                return Ok(false);
+            }
+        }
+
+        self.write_line(note)?;
+
+        if self.options.profiles && self.authors.insert(*note.pubkey()) {
+            if let Some(profile) = self.profile_note(note.pubkey()) {
+                let written = self.seen.as_ref().is_some_and(|s| s.contains(profile.id()));
+                if profile.id() != id && !written && self.profiles.insert(*profile.id()) {
+                    self.write_line(&profile)?;
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn profile_note(&self, pubkey: &[u8; 32]) -> Option<Note<'a>> {
+        let record = self.ndb.get_profile_by_pubkey(self.txn, pubkey).ok()?;
+        let note_key = NoteKey::new(record.record().note_key());
+        self.ndb.get_note_by_key(self.txn, note_key).ok()
+    }
+
+    fn write_line(&mut self, note: &Note<'a>) -> Result<()> {
+        let json = note.json()?;
+
+        if !self.options.relays {
+            writeln!(self.writer, "{json}")?;
+            self.written += 1;
+            return Ok(());
+        }
+
+        let mut relays = String::new();
+        for (i, relay) in note.relays(self.txn).enumerate() {
+            if i > 0 {
+                relays.push(',');
+            }
+            json_string(&mut relays, relay);
+        }
+
+        writeln!(self.writer, r#"{{"event":{json},"relays":[{relays}]}}"#)?;
+        self.written += 1;
+        Ok(())
+    }
+}
+
+/// Append `s` to `out` as a JSON string
+pub(crate) fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::{test_util, IngestMetadata, NoteBuilder};
+
+    #[test]
+    fn export_works() {
+        let db = "target/testdbs/export";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let seckey = [15; 32];
+
+            // three notes per second, so pages split notes that share a
+            // timestamp
+            let mut notes: Vec<Note> = (0..600)
+                .map(|i| {
+                    NoteBuilder::new()
+                        .kind(1)
+                        .content(&format!("note {i}"))
+                        .created_at(1000 + i / 3)
+                        .sign(&seckey)
+                        .build()
+                        .expect("note")
+                })
+                .collect();
+            let profile = NoteBuilder::new()
+                .kind(0)
+                .content(r#"{"name":"exporter"}"#)
+                .created_at(1)
+                .sign(&seckey)
+                .build()
+                .expect("profile");
+            notes.push(profile.clone());
+            test_util::ingest_notes(&ndb, &notes);
+
+            let json = notes[599].json().expect("json");
+            ndb.process_event_with(
+                &format!(r#"["EVENT","s",{json}]"#),
+                IngestMetadata::new()
+                    .client(false)
+                    .relay("wss://relay.example"),
+            )
+            .expect("process ok");
+            for _ in 0..100 {
+                let txn = Transaction::new(&ndb).expect("txn");
+                let note = ndb.get_note_by_id(&txn, notes[599].id()).expect("note");
+                if note.relays(&txn).next().is_some() {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let filter = Filter::new().kinds([1]).build();
+
+            let mut out = vec![];
+            let written = ndb
+                .export(
+                    &txn,
+                    std::slice::from_ref(&filter),
+                    &mut out,
+                    ExportOptions::new(),
+                )
+                .expect("export");
+            assert_eq!(written, 600);
+            let lines: Vec<&str> = std::str::from_utf8(&out).expect("utf8").lines().collect();
+            assert_eq!(lines.len(), 600);
+            let ids: HashSet<[u8; 32]> = lines
+                .iter()
+                .map(|line| *Note::from_json(line).expect("note").id())
+                .collect();
+            assert_eq!(ids.len(), 600);
+
+            let mut out = vec![];
+            let options = ExportOptions::new().relays(true).profiles(true);
+            let written = ndb
+                .export(&txn, &[filter.limit_mut(2)], &mut out, options)
+                .expect("export");
+            assert_eq!(written, 3);
+            let out = String::from_utf8(out).expect("utf8");
+            let lines: Vec<&str> = out.lines().collect();
+            assert!(lines[0].starts_with(r#"{"event":{"id":""#));
+            assert!(lines[0].ends_with(r#","relays":["wss://relay.example"]}"#));
+            assert!(lines[1].contains(r#""kind":0"#));
+            assert!(lines[2].ends_with(r#","relays":[]}"#));
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -264,6 +264,71 @@ impl Filter {
         }
     }
 
+    /// Copy this filter, dropping its limit unless `keep_limit` is set, and
+    /// end the copy with a custom element that runs `closure`. A custom
+    /// element this filter already has keeps running first, so `closure`
+    /// only sees the notes that match everything else.
+    ///
+    /// Fails with [FilterError::TooManyFields] when the copy would need more
+    /// than `NDB_NUM_FILTERS` fields, and with
+    /// [FilterError::UnsupportedElement] when a tag field has a custom
+    /// element, since those can't be copied.
+    ///
+    /// # Safety
+    ///
+    /// `closure` may borrow, which the copy doesn't keep track of. The copy
+    /// has to be dropped before anything `closure` borrows.
+    pub(crate) unsafe fn copy_with_custom<'b, F>(
+        &self,
+        keep_limit: bool,
+        mut closure: F,
+    ) -> Result<Filter>
+    where
+        F: FnMut(Note<'_>) -> bool + 'b,
+    {
+        let fields: Vec<FilterField> = self
+            .into_iter()
+            .filter(|field| match field {
+                FilterField::Custom(_) => false,
+                FilterField::Limit(_) => keep_limit,
+                _ => true,
+            })
+            .collect();
+
+        if fields.len() >= bindings::NDB_NUM_FILTERS as usize {
+            return Err(FilterError::too_many_fields());
+        }
+
+        let custom_tags = fields.iter().any(|field| match field {
+            FilterField::Tags(_, elems) => elems
+                .into_iter()
+                .any(|elem| matches!(elem, FilterElement::Custom)),
+            _ => false,
+        });
+        if custom_tags {
+            return Err(FilterError::unsupported_element());
+        }
+
+        let own = self.custom_ctx.is_some().then(|| self.clone());
+        let chained = move |note: Note<'_>| {
+            if let Some(ctx) = own.as_ref().and_then(|own| own.custom_ctx.as_ref()) {
+                let own_closure = **ctx as *mut Box<dyn FnMut(Note) -> bool>;
+                // SAFETY: the context is kept alive by our clone of the filter
+                let own_note = Note::new_unowned(unsafe { &*note.as_ptr() });
+                if !unsafe { (*own_closure)(own_note) } {
+                    return false;
+                }
+            }
+            closure(note)
+        };
+
+        let boxed: Box<dyn FnMut(Note<'_>) -> bool + 'b> = Box::new(chained);
+        // SAFETY: the caller drops the copy before anything `closure` borrows
+        let boxed: Box<dyn FnMut(Note<'_>) -> bool> = unsafe { std::mem::transmute(boxed) };
+
+        Ok(Filter::copy_from(fields).custom(boxed).build())
+    }
+
     pub fn num_elements(&self) -> i32 {
         unsafe { &*(self.as_ptr()) }.num_elements
     }
@@ -0,0 +1,125 @@
+//! An in-memory index from each pubkey to the authors whose newest contact
+//! list follows it, for [Ndb::followers]. Like the trending hashtags, it is
+//! filled in by the writer thread through a subscription whose custom
+//! filter element indexes each new contact list and then rejects it.
+
+use crate::query::walk;
+use crate::util::nip02::CONTACTS_KIND;
+use crate::{Filter, Ndb, Note, Result, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// The newest contact list seen from an author
+#[derive(Debug)]
+struct ContactList {
+    id: [u8; 32],
+    created_at: u64,
+    follows: HashSet<[u8; 32]>,
+}
+
+#[derive(Debug, Default)]
+struct FollowerIndex {
+    /// author -> their newest contact list
+    lists: HashMap<[u8; 32], ContactList>,
+    /// followed pubkey -> authors following it
+    followers: HashMap<[u8; 32], HashSet<[u8; 32]>>,
+}
+
+impl FollowerIndex {
+    /// Index `note` if it is newer than the author's contact list so far,
+    /// taking the author out of the followers of everyone it replaced.
+    fn add(&mut self, note: &Note) {
+        let author = *note.pubkey();
+        if let Some(old) = self.lists.get(&author) {
+            if old.created_at >= note.created_at() {
+                return;
+            }
+
+            for followed in &old.follows {
+                if let Some(followers) = self.followers.get_mut(followed) {
+                    followers.remove(&author);
+                    if followers.is_empty() {
+                        self.followers.remove(followed);
+                    }
+                }
+            }
+        }
+
+        let mut follows = HashSet::new();
+        for tag in note.tags() {
+            if tag.count() < 2 || tag.get_str(0) != Some("p") {
+                continue;
+            }
+
+            if let Some(followed) = tag.get_id(1) {
+                follows.insert(*followed);
+            }
+        }
+
+        for followed in &follows {
+            self.followers.entry(*followed).or_default().insert(author);
+        }
+
+        self.lists.insert(
+            author,
+            ContactList {
+                id: *note.id(),
+                created_at: note.created_at(),
+                follows,
+            },
+        );
+    }
+
+    fn followers(&self, pubkey: &[u8; 32]) -> Vec<[u8; 32]> {
+        let Some(authors) = self.followers.get(pubkey) else {
+            return vec![];
+        };
+
+        authors
+            .iter()
+            .filter_map(|author| self.lists.get(author).map(|list| list.id))
+            .collect()
+    }
+}
+
+/// Keeps a [FollowerIndex] up to date
+#[derive(Debug)]
+pub(crate) struct FollowerTracker {
+    index: Arc<Mutex<FollowerIndex>>,
+    _filter: Filter,
+}
+
+impl FollowerTracker {
+    /// Subscribe to new contact lists, then index the ones already stored.
+    /// Subscribing first means none are missed in between, and indexing a
+    /// list twice changes nothing.
+    pub(crate) fn start(ndb: &Ndb) -> Result<Self> {
+        let index = Arc::new(Mutex::new(FollowerIndex::default()));
+
+        let writer_index = index.clone();
+        let filter = Filter::new()
+            .kinds([CONTACTS_KIND as u64])
+            .custom(move |note| {
+                writer_index.lock().unwrap().add(&note);
+                false
+            })
+            .build();
+        ndb.subscribe(std::slice::from_ref(&filter))?;
+
+        {
+            let txn = Transaction::new(ndb)?;
+            let contacts = Filter::new().kinds([CONTACTS_KIND as u64]).build();
+            walk(&txn, &contacts, |note| index.lock().unwrap().add(note))?;
+        }
+
+        Ok(FollowerTracker {
+            index,
+            _filter: filter,
+        })
+    }
+
+    /// The ids of the newest contact lists that follow `pubkey`
+    pub(crate) fn contact_lists_following(&self, pubkey: &[u8; 32]) -> Vec<[u8; 32]> {
+        self.index.lock().unwrap().followers(pubkey)
+    }
+}
@@ -0,0 +1,75 @@
+use crate::bindings;
+
+/// A secp256k1 secret key and its x-only public key, for signing and
+/// encrypting notes
+#[derive(Clone, Eq, PartialEq)]
+pub struct Keypair {
+    pubkey: [u8; 32],
+    secret: [u8; 32],
+}
+
+impl Keypair {
+    /// The keypair for a secret key. Returns `None` if it isn't a valid
+    /// secp256k1 secret key.
+    pub fn from_secret(secret: &[u8; 32]) -> Option<Self> {
+        let mut keypair = bindings::ndb_keypair {
+            secret: *secret,
+            ..Default::default()
+        };
+        let created = unsafe { bindings::ndb_create_keypair(&mut keypair) } != 0;
+        wipe(&mut keypair.secret);
+        if !created {
+            return None;
+        }
+
+        Some(Keypair {
+            pubkey: keypair.pubkey,
+            secret: *secret,
+        })
+    }
+
+    /// A new random keypair
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        loop {
+            random_bytes(&mut secret);
+            if let Some(keypair) = Keypair::from_secret(&secret) {
+                wipe(&mut secret);
+                return keypair;
+            }
+        }
+    }
+
+    pub fn pubkey(&self) -> &[u8; 32] {
+        &self.pubkey
+    }
+
+    pub fn secret_key(&self) -> &[u8; 32] {
+        &self.secret
+    }
+}
+
+impl std::fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keypair")
+            .field("pubkey", &self.pubkey)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The secret key is wiped from memory when the keypair is dropped
+impl Drop for Keypair {
+    fn drop(&mut self) {
+        wipe(&mut self.secret);
+    }
+}
+
+/// Overwrite `buf` with zeros in a way the compiler can't optimize away
+pub(crate) fn wipe(buf: &mut [u8]) {
+    unsafe { libsodium_sys::sodium_memzero(buf.as_mut_ptr() as *mut libc::c_void, buf.len()) }
+}
+
+/// Fill `buf` from libsodium's CSPRNG
+pub(crate) fn random_bytes(buf: &mut [u8]) {
+    unsafe { libsodium_sys::randombytes_buf(buf.as_mut_ptr() as *mut libc::c_void, buf.len()) }
+}
@@ -12,14 +12,21 @@ mod bindings;
 #[allow(mismatched_lifetime_syntaxes)]
 mod ndb_profile;
 
+mod aggregate;
+mod bech32;
 mod block;
 
 mod future;
 
 mod config;
+mod count;
 mod error;
+mod export;
 mod filter;
+mod followers;
 mod ingest;
+mod keypair;
+mod lmdb;
 mod metadata;
 mod ndb;
 mod ndb_str;
@@ -27,19 +34,29 @@ mod note;
 mod profile;
 mod query;
 mod relay;
+mod render;
 mod result;
+mod secp;
 mod subscription;
 mod tags;
 mod transaction;
+mod trending;
 mod util;
 
-pub use block::{Block, BlockType, Blocks, Mention};
+pub use aggregate::{Group, GroupBy, GroupKey};
+pub use bech32::Bech32;
+pub use block::{
+    Bech32Type, Block, BlockType, Blocks, Invoice, InvoiceDescription, Mention, ResolvedMention,
+};
 pub use config::Config;
-pub use error::{Error, FilterError};
+pub use count::{Count, CountOptions};
+pub use error::{Error, FilterError, Nip04Error, Nip44Error};
+pub use export::ExportOptions;
 pub use filter::{Filter, FilterBuilder, FilterElement, FilterField, MutFilterField};
 pub(crate) use future::SubscriptionState;
 pub use future::SubscriptionStream;
 pub use ingest::IngestMetadata;
+pub use keypair::Keypair;
 pub use metadata::{
     Counts, CountsEntry, NoteMetadata, NoteMetadataBuf, NoteMetadataBuilder, NoteMetadataEntry,
     NoteMetadataEntryBuf, NoteMetadataEntryVariant, ReactionEntry,
@@ -49,12 +66,27 @@ pub use ndb_profile::{NdbProfile, NdbProfileRecord};
 pub use ndb_str::{NdbStr, NdbStrVariant};
 pub use note::{Note, NoteBuildOptions, NoteBuilder, NoteKey};
 pub use profile::{ProfileKey, ProfileRecord};
-pub use query::QueryResult;
+pub use query::{QueryOptions, QueryResult};
 pub use relay::NoteRelays;
+pub use render::{render_html, render_markdown, DefaultHooks, ProfileNames, RenderHooks};
 pub use result::Result;
 pub use subscription::Subscription;
 pub use tags::{Tag, TagIter, Tags, TagsIter};
 pub use transaction::Transaction;
+pub use trending::TrendingHashtag;
+pub use util::address::NoteAddress;
+pub use util::nip02::Follow;
+pub use util::nip04;
+pub use util::nip09::Deletion;
 pub use util::nip10::{Marker, NoteIdRef, NoteIdRefBuf, NoteReply, NoteReplyBuf};
+pub use util::nip17::{DirectMessage, DmConversation};
+pub use util::nip18::{Quote, Repost};
+pub use util::nip22::{CommentScope, CommentTarget, NoteComment};
+pub use util::nip44;
+pub use util::nip51::{List, ListItem, MuteList};
+pub use util::nip57::{Zap, ZapTotal};
+pub use util::nip59::GiftWrap;
+pub use util::thread::{Thread, ThreadNode};
+pub use util::wot::WotScore;
 
 mod test_util;
@@ -0,0 +1,49 @@
+//! The LMDB calls we need for things nostrdb doesn't do itself, like
+//! copying the database. nostrdb links these in, but they aren't part of
+//! its headers, so they aren't in the generated bindings.
+
+use crate::{Error, Ndb, Result, Transaction};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::{panic, thread};
+
+const MDB_CP_COMPACT: c_uint = 0x01;
+
+extern "C" {
+    fn mdb_txn_env(txn: *mut c_void) -> *mut c_void;
+    fn mdb_env_copy2(env: *mut c_void, path: *const c_char, flags: c_uint) -> c_int;
+}
+
+/// The LMDB environment behind nostrdb, found through a short-lived read
+/// transaction. It lives as long as the [Ndb].
+fn env(ndb: &Ndb) -> Result<*mut c_void> {
+    let txn = Transaction::new(ndb)?;
+    Ok(unsafe { mdb_txn_env((*txn.as_ptr()).mdb_txn) })
+}
+
+/// Write a consistent copy of the database into the existing, empty
+/// directory `dir`. This reads from a snapshot, so writes can go on while it
+/// runs. Compacting leaves out free pages and renumbers the rest, which
+/// takes longer but gives a smaller file.
+///
+/// LMDB allows one read transaction per thread and the copy needs its own,
+/// so it runs on a thread of its own, and callers can keep a [Transaction]
+/// open.
+pub(crate) fn copy(ndb: &Ndb, dir: &str, compact: bool) -> Result<()> {
+    let dir = CString::new(dir)?;
+    let flags = if compact { MDB_CP_COMPACT } else { 0 };
+
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let env = env(ndb)?;
+                if unsafe { mdb_env_copy2(env, dir.as_ptr(), flags) } != 0 {
+                    return Err(Error::BackupFailed);
+                }
+
+                Ok(())
+            })
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+    })
+}
@@ -1,25 +1,44 @@
 use std::ffi::CString;
 use std::ptr;
 
+use crate::aggregate;
 use crate::bindings::ndb_search;
+use crate::count;
+use crate::export;
+use crate::followers::FollowerTracker;
+use crate::lmdb;
+use crate::query::{self, Visibility};
+use crate::trending::HashtagTracker;
+use crate::util::nip09::Deletions;
+use crate::util::{nip02, nip17, nip51, nip59, wot};
 use crate::{
-    bindings, Blocks, Config, Error, Filter, IngestMetadata, Note, NoteKey, NoteMetadata,
-    ProfileKey, ProfileRecord, QueryResult, Result, Subscription, SubscriptionState,
-    SubscriptionStream, Transaction,
+    bindings, Bech32, Blocks, Config, Count, CountOptions, DirectMessage, DmConversation, Error,
+    ExportOptions, Filter, Follow, Group, GroupBy, IngestMetadata, Keypair, List, Mention,
+    MuteList, Note, NoteKey, NoteMetadata, ProfileKey, ProfileRecord, QueryOptions, QueryResult,
+    ResolvedMention, Result, Subscription, SubscriptionState, SubscriptionStream, Transaction,
+    TrendingHashtag, WotScore,
 };
 use futures::StreamExt;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::{self, Write};
 use std::os::raw::c_int;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
 use tracing::debug;
 
 #[derive(Debug)]
 struct NdbRef {
     ndb: *mut bindings::ndb,
     rust_cb_ctx: *mut ::std::os::raw::c_void,
+    /// Dropped after the database is destroyed, since its subscription
+    /// filter is used by the writer thread
+    trending: OnceLock<HashtagTracker>,
+    /// Same as `trending`
+    followers: OnceLock<FollowerTracker>,
+    /// Boxed, since the ingester threads have a pointer to it
+    key_filter: Box<KeyFilter>,
 }
 
 /// SAFETY: thread safety is ensured by nostrdb
@@ -42,6 +61,57 @@ impl Drop for NdbRef {
     }
 }
 
+/// The ingest filter [Ndb::new] puts in front of the configured one.
+/// nostrdb's ingester threads keep every key they are given, so this
+/// rejects giftwraps to keys removed with [Ndb::remove_key] before they get
+/// unwrapped. The rumors inside can't be rejected, since nostrdb leaks them
+/// when they are.
+#[derive(Debug)]
+struct KeyFilter {
+    /// Secret keys added with [Ndb::add_key], by pubkey
+    keys: Mutex<HashMap<[u8; 32], Keypair>>,
+    /// Pubkeys whose secret keys have been sent to the ingester threads.
+    /// nostrdb has no way to take them back out.
+    ingester_keys: Mutex<HashSet<[u8; 32]>>,
+    next: bindings::ndb_ingest_filter_fn,
+    next_ctx: *mut ::std::os::raw::c_void,
+}
+
+impl KeyFilter {
+    /// Whether the ingester threads can still unwrap giftwraps to `pubkey`,
+    /// even though its key was removed. This runs on the ingester threads,
+    /// so it doesn't panic on a poisoned lock.
+    fn is_removed(&self, pubkey: &[u8; 32]) -> bool {
+        let ingester_keys = self
+            .ingester_keys
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let keys = self.keys.lock().unwrap_or_else(PoisonError::into_inner);
+        ingester_keys.contains(pubkey) && !keys.contains_key(pubkey)
+    }
+}
+
+unsafe extern "C" fn key_filter_trampoline(
+    ctx: *mut ::std::os::raw::c_void,
+    note: *mut bindings::ndb_note,
+) -> bindings::ndb_ingest_filter_action {
+    // SAFETY: ctx is the KeyFilter in NdbRef, which outlives the ingesters
+    let filter = &*(ctx as *const KeyFilter);
+
+    let wrap = Note::new_unowned(&*note);
+    if wrap.kind() == nip59::GIFT_WRAP_KIND && !wrap.is_rumor() {
+        let removed = nip59::recipient(&wrap).is_some_and(|to| filter.is_removed(&to));
+        if removed {
+            return bindings::ndb_ingest_filter_action_NDB_INGEST_REJECT;
+        }
+    }
+
+    match filter.next {
+        Some(next) => next(filter.next_ctx, note),
+        None => bindings::ndb_ingest_filter_action_NDB_INGEST_ACCEPT,
+    }
+}
+
 type SubMap = HashMap<Subscription, SubscriptionState>;
 
 /// A nostrdb context. Construct one of these with [Ndb::new].
@@ -94,6 +164,15 @@ impl Ndb {
             }
         });
 
+        let keys = Box::new(KeyFilter {
+            keys: Mutex::new(HashMap::new()),
+            ingester_keys: Mutex::new(HashSet::new()),
+            next: config.config.ingest_filter,
+            next_ctx: config.config.filter_context,
+        });
+        config.config.ingest_filter = Some(key_filter_trampoline);
+        config.config.filter_context = &*keys as *const KeyFilter as *mut ::std::os::raw::c_void;
+
         let result = loop {
             let result =
                 unsafe { bindings::ndb_init(&mut ndb, db_dir_cstr.as_ptr(), config.as_ptr()) };
@@ -118,9 +197,28 @@ impl Ndb {
         }
 
         let rust_cb_ctx = config.config.sub_cb_ctx;
-        let refs = Arc::new(NdbRef { ndb, rust_cb_ctx });
+        let trending_window = config.trending_window;
+        let follower_index = config.follower_index;
+        let refs = Arc::new(NdbRef {
+            ndb,
+            rust_cb_ctx,
+            trending: OnceLock::new(),
+            followers: OnceLock::new(),
+            key_filter: keys,
+        });
+        let ndb = Ndb { refs, subs };
+
+        if let Some(window) = trending_window {
+            let tracker = HashtagTracker::start(&ndb, window)?;
+            let _ = ndb.refs.trending.set(tracker);
+        }
 
-        Ok(Ndb { refs, subs })
+        if follower_index {
+            let tracker = FollowerTracker::start(&ndb)?;
+            let _ = ndb.refs.followers.set(tracker);
+        }
+
+        Ok(ndb)
     }
 
     /// Ingest a relay or client sent event, with optional relay metadata.
@@ -159,51 +257,509 @@ impl Ndb {
         self.process_event_with(json, IngestMetadata::new().client(true))
     }
 
-    /// Attempt to unwrap any unprocessed giftwraps
+    /// Attempt to unwrap the newest giftwrap that hasn't been unwrapped yet,
+    /// with every key added with [Ndb::add_key]. nostrdb only reprocesses
+    /// one giftwrap per call. Nothing happens if that giftwrap is for a key
+    /// that was removed with [Ndb::remove_key].
     pub fn process_giftwraps(&self, txn: &Transaction) {
+        let removed = match self.next_giftwrap_recipient(txn) {
+            Ok(recipient) => recipient.is_some_and(|to| self.refs.key_filter.is_removed(&to)),
+            Err(_) => true,
+        };
+        if removed {
+            return;
+        }
+
+        unsafe {
+            bindings::ndb_process_giftwraps(self.as_ptr(), txn.as_mut_ptr());
+        }
+    }
+
+    /// Like [Ndb::process_giftwraps], but only goes ahead if the giftwrap
+    /// nostrdb would unwrap next is for `pubkey`, whose key has to have been
+    /// added. Returns whether it went ahead.
+    pub fn process_giftwraps_for(&self, txn: &Transaction, pubkey: &[u8; 32]) -> Result<bool> {
+        if !self.added_keys().contains_key(pubkey) {
+            return Ok(false);
+        }
+
+        if self.next_giftwrap_recipient(txn)? != Some(*pubkey) {
+            return Ok(false);
+        }
+
         unsafe {
             bindings::ndb_process_giftwraps(self.as_ptr(), txn.as_mut_ptr());
         }
+        Ok(true)
+    }
+
+    /// Who the giftwrap [Ndb::process_giftwraps] would unwrap next is for.
+    /// Like nostrdb, this takes the newest one not marked as unwrapped.
+    fn next_giftwrap_recipient(&self, txn: &Transaction) -> Result<Option<[u8; 32]>> {
+        let unwrapped = bindings::NDB_NOTE_FLAG_UNWRAPPED as u16;
+        let filter = Filter::new()
+            .kinds([nip59::GIFT_WRAP_KIND as u64])
+            .custom(move |note| note.flags() & unwrapped == 0)
+            .build();
+        let results = query::query_raw(txn, &[filter], 1)?;
+        Ok(results.first().and_then(|res| nip59::recipient(&res.note)))
     }
 
     /// Add a secret key to nostrdb's note ingester threads so that
-    /// nostrdb can unwrap incoming giftwraps.
+    /// nostrdb can unwrap incoming giftwraps. The key is also used to read
+    /// private list entries, like in [Ndb::mute_list] and [Ndb::list].
+    ///
+    /// Giftwraps that are already in the database are only unwrapped by
+    /// [Ndb::process_giftwraps] and [Ndb::process_giftwraps_for].
     pub fn add_key(&self, key: &[u8; 32]) -> bool {
-        unsafe { bindings::ndb_add_key(self.as_ptr(), key as *const u8 as *mut u8) != 0 }
+        let Some(keypair) = Keypair::from_secret(key) else {
+            return false;
+        };
+
+        // a key that was removed is still in the ingester threads. The lock
+        // isn't held while sending it to them, since their ingest filter
+        // takes it too.
+        let ingester_keys = &self.refs.key_filter.ingester_keys;
+        if !ingester_keys.lock().unwrap().contains(keypair.pubkey()) {
+            if unsafe { bindings::ndb_add_key(self.as_ptr(), key as *const u8 as *mut u8) } == 0 {
+                return false;
+            }
+            ingester_keys.lock().unwrap().insert(*keypair.pubkey());
+        }
+
+        self.added_keys().insert(*keypair.pubkey(), keypair);
+        true
+    }
+
+    /// Forget the secret key for `pubkey`, eg. when its account logs out.
+    /// Returns false if it wasn't added.
+    ///
+    /// Its private list entries can no longer be read, and giftwraps to
+    /// `pubkey` are no longer unwrapped. Ones that were stored before are
+    /// left as they are, but new ones are rejected, since nostrdb would
+    /// unwrap them on the way in.
+    ///
+    /// Our copy of the key is wiped, but nostrdb's ingester threads have no
+    /// way to forget a key, so theirs stays in memory until the database is
+    /// closed.
+    pub fn remove_key(&self, pubkey: &[u8; 32]) -> bool {
+        // dropping the keypair wipes it
+        self.added_keys().remove(pubkey).is_some()
+    }
+
+    /// The pubkeys whose secret keys have been added with [Ndb::add_key],
+    /// in order
+    pub fn keys(&self) -> Vec<[u8; 32]> {
+        let mut keys: Vec<[u8; 32]> = self.added_keys().keys().copied().collect();
+        keys.sort();
+        keys
+    }
+
+    fn added_keys(&self) -> MutexGuard<'_, HashMap<[u8; 32], Keypair>> {
+        self.refs.key_filter.keys.lock().unwrap()
+    }
+
+    /// The keypair for `pubkey`, if its secret key was added with
+    /// [Ndb::add_key]
+    pub(crate) fn keypair(&self, pubkey: &[u8; 32]) -> Option<Keypair> {
+        self.added_keys().get(pubkey).cloned()
     }
 
+    /// Query the database. Replaceable and addressable notes that have been
+    /// replaced by a newer version, and notes deleted by their author, are
+    /// left out while scanning, so they don't count towards `max_results` or
+    /// filter limits. Use [Ndb::query_with] to include them.
+    ///
+    /// This costs more than a plain index scan: the first replaceable or
+    /// addressable note seen at each address takes another lookup for the
+    /// newest version, and the first note from each author reads all of
+    /// their deletion requests. Include both with [QueryOptions] to skip
+    /// these checks.
     pub fn query<'a>(
         &self,
         txn: &'a Transaction,
         filters: &[Filter],
         max_results: i32,
     ) -> Result<Vec<QueryResult<'a>>> {
-        let mut ndb_filters: Vec<bindings::ndb_filter> = filters.iter().map(|a| a.data).collect();
-        let mut out: Vec<bindings::ndb_query_result> = vec![];
-        let mut returned: i32 = 0;
-        out.reserve_exact(max_results as usize);
-        let res = unsafe {
-            bindings::ndb_query(
-                txn.as_mut_ptr(),
-                ndb_filters.as_mut_ptr(),
-                ndb_filters.len() as i32,
-                out.as_mut_ptr(),
-                max_results,
-                &mut returned as *mut i32,
-            )
+        self.query_with(txn, filters, max_results, QueryOptions::new())
+    }
+
+    /// Like [Ndb::query], but includes older versions of replaceable and
+    /// addressable notes.
+    pub fn query_all_versions<'a>(
+        &self,
+        txn: &'a Transaction,
+        filters: &[Filter],
+        max_results: i32,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        let options = QueryOptions::new().old_versions(true);
+        self.query_with(txn, filters, max_results, options)
+    }
+
+    /// Query the database, choosing which replaced or deleted notes to
+    /// include with [QueryOptions].
+    pub fn query_with<'a>(
+        &self,
+        txn: &'a Transaction,
+        filters: &[Filter],
+        max_results: i32,
+        options: QueryOptions,
+    ) -> Result<Vec<QueryResult<'a>>> {
+        query::query(txn, filters, max_results, options)
+    }
+
+    /// Count the notes matching any of `filters`, as NIP-45 `COUNT` does.
+    /// This counts the same notes [Ndb::query] would return, ignoring
+    /// limits, without collecting them.
+    pub fn count(&self, txn: &Transaction, filters: &[Filter]) -> Result<u64> {
+        Ok(self.count_with(txn, filters, CountOptions::new())?.count)
+    }
+
+    /// Like [Ndb::count], with an approximate mode for very large counts.
+    /// See [CountOptions].
+    pub fn count_with(
+        &self,
+        txn: &Transaction,
+        filters: &[Filter],
+        options: CountOptions,
+    ) -> Result<Count> {
+        count::count(txn, filters, options)
+    }
+
+    /// Count the notes matching any of `filters` by author, kind, tag
+    /// value or time bucket, from the indices rather than copies of the
+    /// notes. Like [Ndb::count], replaced and deleted notes are left out.
+    /// Time buckets come back oldest first, other groups most common first.
+    pub fn aggregate(
+        &self,
+        txn: &Transaction,
+        filters: &[Filter],
+        group_by: GroupBy,
+    ) -> Result<Vec<Group>> {
+        aggregate::aggregate(txn, filters, group_by)
+    }
+
+    /// The hashtags used most since `since` (unix seconds, to the minute),
+    /// with how many notes and authors used each. Hashtags come from `t`
+    /// tags and from `#hashtags` in the content.
+    ///
+    /// This comes from an in-memory index kept up to date as notes are
+    /// written, which has to be turned on with
+    /// [Config::set_trending_window]. Returns [Error::NotFound] otherwise.
+    /// The index isn't stored, so opening the database rebuilds it by
+    /// walking every note in the window, and `since` can't reach further
+    /// back than the window. Notes deleted after they were counted stay
+    /// counted.
+    pub fn trending_hashtags(&self, since: u64, limit: usize) -> Result<Vec<TrendingHashtag>> {
+        let tracker = self.refs.trending.get().ok_or(Error::NotFound)?;
+        Ok(tracker.trending(since, limit))
+    }
+
+    /// Who `pubkey` follows, from their newest contact list (kind 3).
+    /// Empty if we don't have one.
+    pub fn follows<'a>(&self, txn: &'a Transaction, pubkey: &[u8; 32]) -> Result<Vec<Follow<'a>>> {
+        match self.get_replaceable(txn, nip02::CONTACTS_KIND, pubkey) {
+            Ok(contacts) => Ok(nip02::follows(&contacts)),
+            Err(Error::NotFound) => Ok(vec![]),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Everyone whose newest contact list follows `pubkey`, with the relay
+    /// hint and petname they use for it. Contact lists that have since been
+    /// replaced are skipped, and so is everyone whose newest contact list
+    /// was deleted.
+    ///
+    /// With [Config::set_follower_index], the followers come from an index
+    /// kept up to date as contact lists are written. Otherwise every stored
+    /// contact list that ever followed `pubkey` is walked through the `p`
+    /// tag index.
+    pub fn followers<'a>(
+        &self,
+        txn: &'a Transaction,
+        pubkey: &[u8; 32],
+    ) -> Result<Vec<Follow<'a>>> {
+        let ids = match self.refs.followers.get() {
+            Some(tracker) => tracker.contact_lists_following(pubkey),
+            None => {
+                let filter = Filter::new()
+                    .kinds([nip02::CONTACTS_KIND as u64])
+                    .pubkey([pubkey])
+                    .build();
+                // only the newest list of each author gets through, so
+                // there are no duplicates
+                let mut newest = Visibility::new(txn, QueryOptions::new().deleted(true));
+                let mut ids = vec![];
+                query::walk(txn, &filter, |note| {
+                    if newest.keep(note) {
+                        ids.push(*note.id());
+                    }
+                })?;
+                newest.finish()?;
+                ids
+            }
         };
-        if res == 1 {
-            unsafe {
-                out.set_len(returned as usize);
+
+        let mut deletions = Deletions::default();
+        let mut followers = vec![];
+        for id in ids {
+            let contacts = match self.get_note_by_id(txn, &id) {
+                Ok(contacts) => contacts,
+                Err(Error::NotFound) => continue,
+                Err(err) => return Err(err),
             };
-            Ok(out.iter().map(|r| QueryResult::new(r, txn)).collect())
-        } else {
-            Err(Error::QueryError)
+            if deletions.is_deleted(txn, &contacts)? {
+                continue;
+            }
+
+            let follow = nip02::follows(&contacts)
+                .into_iter()
+                .find(|follow| follow.followed == pubkey);
+            followers.extend(follow);
         }
+
+        Ok(followers)
+    }
+
+    /// How many follows it takes to get from `from` to `to` through
+    /// contact lists in the database, or `None` if it takes more than
+    /// `max_hops`.
+    pub fn wot_distance(
+        &self,
+        txn: &Transaction,
+        from: &[u8; 32],
+        to: &[u8; 32],
+        max_hops: u32,
+    ) -> Result<Option<u32>> {
+        wot::distance(self, txn, from, to, max_hops)
     }
 
+    /// Trust everyone within `depth` follows of `root`, and rank everyone
+    /// they follow by how many of them follow it, most trusted first.
+    /// With a depth of 1, that is how many of root's follows follow them.
+    pub fn wot_scores(
+        &self,
+        txn: &Transaction,
+        root: &[u8; 32],
+        depth: u32,
+    ) -> Result<Vec<WotScore>> {
+        wot::scores(self, txn, root, depth)
+    }
+
+    /// Our one-to-one private conversations, newest first, each with its
+    /// latest message. This covers NIP-17 messages unwrapped with a key
+    /// added through [Ndb::add_key], and legacy kind 4 DMs.
+    pub fn dm_conversations<'a>(
+        &self,
+        txn: &'a Transaction,
+        my_pubkey: &[u8; 32],
+    ) -> Result<Vec<DmConversation<'a>>> {
+        nip17::conversations(self, txn, my_pubkey)
+    }
+
+    /// The private messages between us and `peer`, oldest first
+    pub fn dm_messages<'a>(
+        &self,
+        txn: &'a Transaction,
+        my_pubkey: &[u8; 32],
+        peer: &[u8; 32],
+    ) -> Result<Vec<DirectMessage<'a>>> {
+        nip17::messages(self, txn, my_pubkey, peer)
+    }
+
+    /// The newest mute list (kind 10000) of `pubkey`, or an empty one. Its
+    /// private entries are included if the pubkey's secret key has been
+    /// added with [Ndb::add_key].
+    pub fn mute_list(&self, txn: &Transaction, pubkey: &[u8; 32]) -> Result<MuteList> {
+        let note = match self.get_replaceable(txn, nip51::MUTE_LIST_KIND, pubkey) {
+            Ok(note) => note,
+            Err(Error::NotFound) => return Ok(MuteList::new()),
+            Err(err) => return Err(err),
+        };
+
+        let keypair = self.keypair(pubkey);
+        let seckey = keypair.as_ref().map(Keypair::secret_key);
+        Ok(MuteList::from_note(&note, seckey).unwrap_or_default())
+    }
+
+    /// The newest version of a NIP-51 list, like bookmarks (kind 10003),
+    /// by `pubkey`. Its private items are included if the pubkey's secret
+    /// key has been added with [Ndb::add_key]. Kinds that aren't NIP-51
+    /// lists are [Error::NotFound].
+    pub fn list(&self, txn: &Transaction, kind: u32, pubkey: &[u8; 32]) -> Result<List> {
+        let note = self.get_replaceable(txn, kind, pubkey)?;
+        let keypair = self.keypair(pubkey);
+        List::from_note(&note, keypair.as_ref().map(Keypair::secret_key)).ok_or(Error::NotFound)
+    }
+
+    /// Like [Ndb::list], for a set identified by its `d` tag, like a follow
+    /// set (kind 30000).
+    pub fn list_set(
+        &self,
+        txn: &Transaction,
+        kind: u32,
+        pubkey: &[u8; 32],
+        identifier: &str,
+    ) -> Result<List> {
+        let note = self.get_addressable(txn, kind, pubkey, identifier)?;
+        let keypair = self.keypair(pubkey);
+        List::from_note(&note, keypair.as_ref().map(Keypair::secret_key)).ok_or(Error::NotFound)
+    }
+
+    /// Write a consistent copy of the database to the directory `path`,
+    /// which must be empty or not exist yet. Ingestion can continue while
+    /// the copy is made. With `compact`, free pages are left out, which
+    /// gives a smaller copy at the cost of a slower one.
+    ///
+    /// The copy is written to a new hidden directory next to `path` and
+    /// only renamed into place once it is complete, so `path` is either a
+    /// full backup or missing. Open it with [Ndb::new] to restore it.
+    pub fn backup_to(&self, path: &str, compact: bool) -> Result<()> {
+        let dest = Path::new(path);
+        if dest.exists() && fs::read_dir(dest).map_or(true, |mut d| d.next().is_some()) {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("backup destination {path} already exists"),
+            )));
+        }
+
+        let Some(name) = dest.file_name() else {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("backup destination {path} has no directory name"),
+            )));
+        };
+        let parent = dest.parent().filter(|p| !p.as_os_str().is_empty());
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let partial = parent.unwrap_or(Path::new(".")).join(format!(
+            ".{}.partial-{}-{nanos}",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+        // fails if it already exists, so we only ever clean up our own
+        fs::create_dir(&partial)?;
+
+        let copied = partial
+            .to_str()
+            .ok_or(Error::DecodeError)
+            .and_then(|dir| lmdb::copy(self, dir, compact))
+            .and_then(|_| {
+                fs::File::open(partial.join("data.mdb"))?.sync_all()?;
+                if dest.exists() {
+                    fs::remove_dir(dest)?;
+                }
+                fs::rename(&partial, dest)?;
+                Ok(())
+            });
+
+        if let Err(err) = copied {
+            return match fs::remove_dir_all(&partial) {
+                Ok(()) => Err(err),
+                Err(cleanup) => Err(Error::IO(io::Error::new(
+                    cleanup.kind(),
+                    format!(
+                        "backup failed: {err}, and removing {} failed: {cleanup}",
+                        partial.display()
+                    ),
+                ))),
+            };
+        }
+
+        // make the rename itself durable
+        if let Some(parent) = parent {
+            fs::File::open(parent)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy the database to `path` as it is right now, like
+    /// [Ndb::backup_to], and open the copy. Long running reads, like
+    /// exports, can use the snapshot instead of holding a [Transaction] on
+    /// this database, which would stop LMDB from reusing freed pages.
+    pub fn open_snapshot(&self, path: &str, config: &Config) -> Result<Ndb> {
+        self.backup_to(path, false)?;
+        Ndb::new(path, config)
+    }
+
+    /// Write every note matching `filters` to `writer` as JSON lines,
+    /// newest first for each filter. Unlike [Ndb::query], there is no cap
+    /// on how many notes are written, other than each filter's own
+    /// `limit`. Replaced versions and deletion requests are exported too, so
+    /// importing the lines elsewhere recreates the same view.
+    ///
+    /// Returns how many lines were written.
+    pub fn export<W: Write>(
+        &self,
+        txn: &Transaction,
+        filters: &[Filter],
+        writer: W,
+        options: ExportOptions,
+    ) -> Result<usize> {
+        export::export(self, txn, filters, writer, options)
+    }
+
+    /// Get the newest version of a replaceable note, such as a profile
+    /// (kind 0), contact list (kind 3) or relay list (kind 10002).
+    pub fn get_replaceable<'a>(
+        &self,
+        txn: &'a Transaction,
+        kind: u32,
+        pubkey: &[u8; 32],
+    ) -> Result<Note<'a>> {
+        let filter = Filter::new().authors([pubkey]).kinds([kind as u64]).build();
+        self.get_latest(txn, filter)
+    }
+
+    /// Get the newest version of an addressable note, such as a kind 30023
+    /// article, from its `kind:pubkey:d-tag` coordinate.
+    pub fn get_addressable<'a>(
+        &self,
+        txn: &'a Transaction,
+        kind: u32,
+        pubkey: &[u8; 32],
+        d_tag: &str,
+    ) -> Result<Note<'a>> {
+        let filter = Filter::new()
+            .authors([pubkey])
+            .kinds([kind as u64])
+            .tags([d_tag], 'd')
+            .build();
+        self.get_latest(txn, filter)
+    }
+
+    /// A deleted newest version counts as not found, without falling back
+    /// to the version before it, which it replaced.
+    fn get_latest<'a>(&self, txn: &'a Transaction, filter: Filter) -> Result<Note<'a>> {
+        let note = self.get_newest(txn, filter)?;
+        if note.find_deletion(txn)?.is_some() {
+            return Err(Error::NotFound);
+        }
+
+        Ok(note)
+    }
+
+    /// The newest version of a replaceable or addressable note, deleted or
+    /// not. Single author + kind filters use the `NOTE_PUBKEY_KIND` index,
+    /// which is scanned newest first.
+    fn get_newest<'a>(&self, txn: &'a Transaction, filter: Filter) -> Result<Note<'a>> {
+        query::query_raw(txn, &[filter], 1)?
+            .into_iter()
+            .next()
+            .map(|res| res.note)
+            .ok_or(Error::NotFound)
+    }
+
+    /// How many subscriptions are active. The ones behind
+    /// [Ndb::trending_hashtags] and the follower index aren't counted,
+    /// though they still take up one of nostrdb's subscription slots each.
     pub fn subscription_count(&self) -> u32 {
-        unsafe { bindings::ndb_num_subscriptions(self.as_ptr()) as u32 }
+        let internal =
+            self.refs.trending.get().is_some() as u32 + self.refs.followers.get().is_some() as u32;
+        let total = unsafe { bindings::ndb_num_subscriptions(self.as_ptr()) as u32 };
+        total.saturating_sub(internal)
     }
 
     pub fn unsubscribe(&mut self, sub: Subscription) -> Result<()> {
@@ -435,30 +991,76 @@ impl Ndb {
         transaction: &'a Transaction,
         id: &[u8; 32],
     ) -> Result<Note<'a>> {
-        let mut len: usize = 0;
-        let mut primkey: u64 = 0;
+        query::note_by_id(transaction, id)
+    }
 
-        let note_ptr = unsafe {
-            bindings::ndb_get_note_by_id(
-                transaction.as_mut_ptr(),
-                id.as_ptr(),
-                &mut len,
-                &mut primkey,
-            )
-        };
+    /// Look up what a mention points at. `npub` and `nprofile` resolve to
+    /// profiles, `note` and `nevent` to notes, and `naddr` to the newest
+    /// note by that author with that `d` tag, of any kind, since mentions
+    /// don't keep the kind. Use [Ndb::resolve_bech32] when it's known.
+    /// `nrelay` and `nsec` mentions don't point at anything in the
+    /// database, so they are [`Error::NotFound`].
+    pub fn resolve_mention<'a>(
+        &self,
+        txn: &'a Transaction,
+        mention: &Mention,
+    ) -> Result<ResolvedMention<'a>> {
+        match mention {
+            Mention::Pubkey(npub) => self
+                .get_profile_by_pubkey(txn, npub.pubkey())
+                .map(ResolvedMention::Profile),
+            Mention::Profile(nprofile) => self
+                .get_profile_by_pubkey(txn, nprofile.pubkey())
+                .map(ResolvedMention::Profile),
+            Mention::Note(note) => self
+                .get_note_by_id(txn, note.id())
+                .map(ResolvedMention::Note),
+            Mention::Event(nevent) => self
+                .get_note_by_id(txn, nevent.id())
+                .map(ResolvedMention::Note),
+            Mention::Addr(naddr) => self.resolve_naddr(txn, naddr, None),
+            Mention::Relay(_) | Mention::Secret(_) => Err(Error::NotFound),
+        }
+    }
 
-        if note_ptr.is_null() {
-            // Handle null pointer (e.g., note not found or error occurred)
-            return Err(Error::NotFound);
+    /// Like [Ndb::resolve_mention], but an `naddr` that includes its kind
+    /// only resolves to the newest note of that kind, like
+    /// [Ndb::get_addressable].
+    pub fn resolve_bech32<'a>(
+        &self,
+        txn: &'a Transaction,
+        bech32: &Bech32,
+    ) -> Result<ResolvedMention<'a>> {
+        match bech32.as_mention() {
+            Mention::Addr(naddr) => self.resolve_naddr(txn, naddr, bech32.kind()),
+            mention => self.resolve_mention(txn, &mention),
         }
+    }
 
-        // Convert the raw pointer to a Note instance
-        Ok(Note::new_transactional(
-            note_ptr,
-            len,
-            NoteKey::new(primkey),
-            transaction,
-        ))
+    fn resolve_naddr<'a>(
+        &self,
+        txn: &'a Transaction,
+        naddr: &bindings::bech32_naddr,
+        kind: Option<u32>,
+    ) -> Result<ResolvedMention<'a>> {
+        let pubkey = naddr.pubkey().ok_or(Error::NotFound)?;
+        if let Some(kind) = kind {
+            return self
+                .get_addressable(txn, kind, pubkey, naddr.identifier())
+                .map(ResolvedMention::Note);
+        }
+
+        // content mentions don't keep the naddr kind, so take the newest
+        // note by that author with that `d` tag, whatever its kind
+        let filter = Filter::new()
+            .authors([pubkey])
+            .tags([naddr.identifier()], 'd')
+            .build();
+        self.query(txn, &[filter], 1)?
+            .into_iter()
+            .next()
+            .map(|res| ResolvedMention::Note(res.note))
+            .ok_or(Error::NotFound)
     }
 
     pub fn search_profile<'a>(
@@ -874,4 +1476,403 @@ mod tests {
 
         test_util::cleanup_db(&db);
     }
+
+    #[test]
+    fn resolve_mention_works() {
+        let db = "target/testdbs/resolve_mention";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let seckey = [9; 32];
+
+            let profile = crate::NoteBuilder::new()
+                .kind(0)
+                .content(r#"{"name":"alice"}"#)
+                .sign(&seckey)
+                .build()
+                .expect("profile");
+            let note = crate::NoteBuilder::new()
+                .kind(1)
+                .content("hello")
+                .sign(&seckey)
+                .build()
+                .expect("note");
+            let old = crate::NoteBuilder::new()
+                .kind(30023)
+                .content("draft")
+                .created_at(1)
+                .start_tag()
+                .tag_str("d")
+                .tag_str("article")
+                .sign(&seckey)
+                .build()
+                .expect("old");
+            let new = crate::NoteBuilder::new()
+                .kind(30023)
+                .content("final")
+                .created_at(2)
+                .start_tag()
+                .tag_str("d")
+                .tag_str("article")
+                .sign(&seckey)
+                .build()
+                .expect("new");
+
+            let other_kind = crate::NoteBuilder::new()
+                .kind(30024)
+                .content("another draft")
+                .created_at(3)
+                .start_tag()
+                .tag_str("d")
+                .tag_str("article")
+                .sign(&seckey)
+                .build()
+                .expect("other kind");
+
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    profile.clone(),
+                    note.clone(),
+                    old.clone(),
+                    new.clone(),
+                    other_kind.clone(),
+                ],
+            );
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let pubkey = note.pubkey();
+
+            let npub = crate::Bech32::decode(&crate::Bech32::encode_npub(pubkey)).unwrap();
+            match ndb.resolve_mention(&txn, &npub.as_mention()).expect("npub") {
+                ResolvedMention::Profile(p) => {
+                    assert_eq!(p.record().profile().unwrap().name(), Some("alice"))
+                }
+                _ => panic!("expected profile"),
+            }
+
+            let nevent = crate::Bech32::encode_nevent(note.id(), &[], None, None).unwrap();
+            let nevent = crate::Bech32::decode(&nevent).unwrap();
+            match ndb
+                .resolve_mention(&txn, &nevent.as_mention())
+                .expect("nevent")
+            {
+                ResolvedMention::Note(n) => assert_eq!(n.id(), note.id()),
+                _ => panic!("expected note"),
+            }
+
+            let address = crate::NoteAddress::new(30023, pubkey, "article");
+            let naddr = crate::Bech32::encode_naddr(&address, &[]).unwrap();
+            let naddr = crate::Bech32::decode(&naddr).unwrap();
+            match ndb.resolve_bech32(&txn, &naddr).expect("naddr") {
+                ResolvedMention::Note(n) => assert_eq!(n.id(), new.id()),
+                _ => panic!("expected note"),
+            }
+            // without the kind, the newest note with that d tag wins
+            match ndb
+                .resolve_mention(&txn, &naddr.as_mention())
+                .expect("naddr")
+            {
+                ResolvedMention::Note(n) => assert_eq!(n.id(), other_kind.id()),
+                _ => panic!("expected note"),
+            }
+
+            let missing = crate::Bech32::decode(&crate::Bech32::encode_note(&[1; 32])).unwrap();
+            assert!(matches!(
+                ndb.resolve_mention(&txn, &missing.as_mention()),
+                Err(Error::NotFound)
+            ));
+        }
+
+        test_util::cleanup_db(db);
+    }
+
+    #[test]
+    fn replaceable_lookup_works() {
+        let db = "target/testdbs/replaceable_lookup";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let seckey = [10; 32];
+            let note = |kind: u32, created_at: u64, d: Option<&str>| {
+                let mut builder = crate::NoteBuilder::new()
+                    .kind(kind)
+                    .content("")
+                    .created_at(created_at);
+                if let Some(d) = d {
+                    builder = builder.start_tag().tag_str("d").tag_str(d);
+                }
+                builder.sign(&seckey).build().expect("note")
+            };
+
+            let relays_old = note(10002, 1, None);
+            let relays_new = note(10002, 2, None);
+            let article_old = note(30023, 1, Some("a"));
+            let article_new = note(30023, 3, Some("a"));
+            let other_article = note(30023, 2, Some("b"));
+
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    relays_old.clone(),
+                    relays_new.clone(),
+                    article_old.clone(),
+                    article_new.clone(),
+                    other_article.clone(),
+                ],
+            );
+
+            let pubkey = relays_new.pubkey();
+            let txn = Transaction::new(&ndb).expect("txn");
+
+            let relays = ndb.get_replaceable(&txn, 10002, pubkey).expect("relays");
+            assert_eq!(relays.id(), relays_new.id());
+
+            let article = ndb
+                .get_addressable(&txn, 30023, pubkey, "a")
+                .expect("article");
+            assert_eq!(article.id(), article_new.id());
+            assert!(matches!(
+                ndb.get_addressable(&txn, 30023, pubkey, "c"),
+                Err(Error::NotFound)
+            ));
+
+            let filter = Filter::new().kinds([10002, 30023]).build();
+            let mut ids: Vec<[u8; 32]> = ndb
+                .query(&txn, std::slice::from_ref(&filter), 10)
+                .expect("query")
+                .iter()
+                .map(|r| *r.note.id())
+                .collect();
+            ids.sort();
+            let mut expected = vec![*relays_new.id(), *article_new.id(), *other_article.id()];
+            expected.sort();
+            assert_eq!(ids, expected);
+
+            let all = ndb.query_all_versions(&txn, &[filter], 10).expect("query");
+            assert_eq!(all.len(), 5);
+            drop((relays, article, all));
+            drop(txn);
+
+            // deleting the newest version doesn't bring back the one it
+            // replaced
+            let deletion = crate::NoteBuilder::new()
+                .kind(5)
+                .content("")
+                .created_at(4)
+                .start_tag()
+                .tag_str("e")
+                .tag_id(article_new.id())
+                .sign(&seckey)
+                .build()
+                .expect("deletion");
+            test_util::ingest_notes(&ndb, &[deletion]);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            assert!(matches!(
+                ndb.get_addressable(&txn, 30023, pubkey, "a"),
+                Err(Error::NotFound)
+            ));
+            // and the limit only counts notes that are returned
+            let filter = Filter::new().kinds([30023]).build();
+            let articles = ndb.query(&txn, &[filter], 1).expect("query");
+            assert_eq!(articles.len(), 1);
+            assert_eq!(articles[0].note.id(), other_article.id());
+
+            // a filter with no room for another field is checked after the
+            // scan instead
+            let mut full = Filter::new()
+                .kinds([30023])
+                .authors([pubkey])
+                .since(1)
+                .until(10)
+                .limit(10);
+            for _ in 0..5 {
+                full = full.tags(["a", "b"], 'd');
+            }
+            let full = full.build();
+            assert_eq!(full.num_elements(), 10);
+            let articles = ndb.query(&txn, &[full], 10).expect("query");
+            assert_eq!(articles.len(), 1);
+            assert_eq!(articles[0].note.id(), other_article.id());
+        }
+
+        test_util::cleanup_db(db);
+    }
+
+    #[test]
+    fn backup_works() {
+        let db = "target/testdbs/backup_source";
+        let backup = "target/testdbs/backup_copy";
+        let compacted = "target/testdbs/backup_compacted";
+        let snapshot = "target/testdbs/backup_snapshot";
+        for dir in [db, backup, compacted, snapshot] {
+            test_util::cleanup_db(dir);
+        }
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let seckey = [14; 32];
+            let notes: Vec<Note> = (0..10)
+                .map(|i| {
+                    crate::NoteBuilder::new()
+                        .kind(1)
+                        .content(&format!("note {i}"))
+                        .created_at(i + 1)
+                        .sign(&seckey)
+                        .build()
+                        .expect("note")
+                })
+                .collect();
+            test_util::ingest_notes(&ndb, &notes[..9]);
+
+            // an open transaction doesn't get in the way
+            let txn = Transaction::new(&ndb).expect("txn");
+            ndb.backup_to(backup, false).expect("backup");
+            drop(txn);
+            ndb.backup_to(compacted, true).expect("compacted backup");
+            assert!(ndb.backup_to(backup, false).is_err());
+            let leftovers = fs::read_dir("target/testdbs")
+                .expect("testdbs")
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().contains(".partial-"))
+                .count();
+            assert_eq!(leftovers, 0);
+
+            let snap = ndb
+                .open_snapshot(snapshot, &Config::new())
+                .expect("snapshot");
+
+            // the copies don't see anything written after them
+            test_util::ingest_notes(&ndb, &notes[9..]);
+            for restored in [
+                Ndb::new(backup, &Config::new()).expect("backup"),
+                Ndb::new(compacted, &Config::new()).expect("compacted"),
+                snap,
+            ] {
+                let txn = Transaction::new(&restored).expect("txn");
+                let filter = Filter::new().kinds([1]).build();
+                let results = restored.query(&txn, &[filter], 20).expect("query");
+                assert_eq!(results.len(), 9);
+                assert!(restored.get_note_by_id(&txn, notes[0].id()).is_ok());
+                assert!(restored.get_note_by_id(&txn, notes[9].id()).is_err());
+            }
+        }
+
+        for dir in [db, backup, compacted, snapshot] {
+            test_util::cleanup_db(dir);
+        }
+    }
+
+    #[test]
+    fn keys_can_be_removed() {
+        let db = "target/testdbs/remove_key";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let alice = Keypair::from_secret(&[20; 32]).expect("alice");
+            let bob = Keypair::from_secret(&[21; 32]).expect("bob");
+
+            let mut bookmarks = crate::List::new(10003, alice.pubkey(), None).expect("list");
+            bookmarks.add_private(crate::ListItem::hashtag("secret"));
+            test_util::ingest_notes(&ndb, &[bookmarks.sign(alice.secret_key()).unwrap()]);
+
+            assert!(ndb.keys().is_empty());
+            assert!(!ndb.add_key(&[0; 32]));
+            assert!(ndb.add_key(alice.secret_key()));
+            assert!(ndb.add_key(bob.secret_key()));
+            let mut expected = vec![*alice.pubkey(), *bob.pubkey()];
+            expected.sort();
+            assert_eq!(ndb.keys(), expected);
+
+            let private = |ndb: &Ndb| {
+                let txn = Transaction::new(ndb).expect("txn");
+                ndb.list(&txn, 10003, alice.pubkey())
+                    .expect("list")
+                    .private_items()
+                    .len()
+            };
+            assert_eq!(private(&ndb), 1);
+
+            assert!(ndb.remove_key(alice.pubkey()));
+            assert!(!ndb.remove_key(alice.pubkey()));
+            assert_eq!(ndb.keys(), vec![*bob.pubkey()]);
+            assert_eq!(private(&ndb), 0);
+
+            // adding it back doesn't load it into the ingesters twice
+            assert!(ndb.add_key(alice.secret_key()));
+            assert_eq!(ndb.refs.key_filter.ingester_keys.lock().unwrap().len(), 2);
+            assert_eq!(private(&ndb), 1);
+
+            let dm = |to: &Keypair, content: &str| {
+                let rumor = crate::NoteBuilder::new()
+                    .kind(14)
+                    .content(content)
+                    .start_tag()
+                    .tag_str("p")
+                    .tag_id(to.pubkey())
+                    .build()
+                    .expect("rumor");
+                crate::GiftWrap::seal_and_wrap(&rumor, &alice, to.pubkey())
+                    .expect("wrap")
+                    .into_wrap()
+            };
+            let count = |ndb: &Ndb, kind: u64| {
+                let txn = Transaction::new(ndb).expect("txn");
+                let filter = Filter::new().kinds([kind]).build();
+                let found = ndb.query(&txn, &[filter], 10).expect("query").len();
+                found
+            };
+            let wait_for_dms = |ndb: &Ndb, n: usize| {
+                for _ in 0..100 {
+                    if count(ndb, 14) >= n {
+                        break;
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                assert_eq!(count(ndb, 14), n);
+            };
+
+            // stored giftwraps are only unwrapped again for keys that are
+            // still added
+            let carol = Keypair::from_secret(&[22; 32]).expect("carol");
+            test_util::ingest_notes(&ndb, &[dm(&carol, "hi carol")]);
+            assert!(ndb.add_key(carol.secret_key()));
+            assert!(ndb.remove_key(carol.pubkey()));
+            let txn = Transaction::new(&ndb).expect("txn");
+            ndb.process_giftwraps(&txn);
+            assert!(!ndb.process_giftwraps_for(&txn, carol.pubkey()).unwrap());
+            assert!(!ndb.process_giftwraps_for(&txn, bob.pubkey()).unwrap());
+            drop(txn);
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            assert_eq!(count(&ndb, 14), 0);
+
+            assert!(ndb.add_key(carol.secret_key()));
+            let txn = Transaction::new(&ndb).expect("txn");
+            assert!(ndb.process_giftwraps_for(&txn, carol.pubkey()).unwrap());
+            drop(txn);
+            wait_for_dms(&ndb, 1);
+
+            // new giftwraps to a removed key are rejected before they get
+            // unwrapped
+            assert!(ndb.remove_key(bob.pubkey()));
+            let to_bob = dm(&bob, "hi bob");
+            let json = to_bob.json().expect("json");
+            ndb.process_event(&format!(r#"["EVENT","test",{json}]"#))
+                .expect("process");
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            assert_eq!(count(&ndb, 1059), 1);
+            assert_eq!(count(&ndb, 14), 1);
+
+            // until the key comes back
+            assert!(ndb.add_key(bob.secret_key()));
+            test_util::ingest_notes(&ndb, &[to_bob]);
+            wait_for_dms(&ndb, 2);
+        }
+
+        test_util::cleanup_db(db);
+    }
 }
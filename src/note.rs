@@ -1,4 +1,8 @@
-use crate::{bindings, tags::Tags, transaction::Transaction, Error, NoteRelays};
+use crate::util::{nip04, nip09};
+use crate::{
+    bindings, secp, tags::Tags, transaction::Transaction, Deletion, Error, Keypair, Nip04Error,
+    NoteRelays, Quote,
+};
 use std::{hash::Hash, os::raw::c_uchar};
 
 #[derive(Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd, Hash)]
@@ -152,6 +156,47 @@ impl<'a> Note<'a> {
         }
     }
 
+    /// Parse a raw note json object, eg: `{"id":"...","pubkey":...}`. The
+    /// signature isn't checked, use [Note::verify] for that.
+    pub fn from_json(json: &str) -> Result<Note<'static>, Error> {
+        // notes are usually much smaller than this, we shrink after parsing
+        Self::from_json_with_bufsize(json, json.len() * 4 + 4096)
+    }
+
+    pub fn from_json_with_bufsize(json: &str, bufsize: usize) -> Result<Note<'static>, Error> {
+        let buf = unsafe { libc::malloc(bufsize) as *mut c_uchar };
+        if buf.is_null() {
+            return Err(Error::BufferOverflow);
+        }
+
+        let mut note_ptr: *mut bindings::ndb_note = std::ptr::null_mut();
+        let size = unsafe {
+            bindings::ndb_note_from_json(
+                json.as_ptr() as *const ::std::os::raw::c_char,
+                json.len() as ::std::os::raw::c_int,
+                &mut note_ptr as *mut *mut bindings::ndb_note,
+                buf,
+                bufsize as ::std::os::raw::c_int,
+            )
+        };
+
+        if size <= 0 || note_ptr.is_null() {
+            unsafe { libc::free(buf as *mut libc::c_void) };
+            return Err(Error::DecodeError);
+        }
+
+        // the note is built at the start of the buffer
+        let note_ptr = unsafe {
+            libc::realloc(buf as *mut libc::c_void, size as usize) as *mut bindings::ndb_note
+        };
+        if note_ptr.is_null() {
+            unsafe { libc::free(buf as *mut libc::c_void) };
+            return Err(Error::BufferOverflow);
+        }
+
+        Ok(Note::new_owned(note_ptr, size as usize))
+    }
+
     #[inline]
     pub fn txn(&'a self) -> Option<&'a Transaction> {
         match self {
@@ -199,6 +244,41 @@ impl<'a> Note<'a> {
             == bindings::NDB_NOTE_FLAG_RUMOR as u16
     }
 
+    /// Look up the NIP-09 deletion request (kind 5) from this note's author
+    /// that deletes it. This queries the database each time; [Ndb::query]
+    /// already leaves deleted notes out.
+    ///
+    /// [Ndb::query]: crate::Ndb::query
+    pub fn find_deletion<'t>(&self, txn: &'t Transaction) -> Result<Option<Deletion<'t>>, Error> {
+        nip09::find_deletion(txn, self)
+    }
+
+    /// Whether this note's author deleted it with a NIP-09 deletion request.
+    /// See [Note::find_deletion].
+    pub fn is_deleted(&self, txn: &Transaction) -> Result<bool, Error> {
+        Ok(self.find_deletion(txn)?.is_some())
+    }
+
+    /// Decrypt the content of a legacy NIP-04 DM (kind 4) that was sent to
+    /// or by `keypair`
+    pub fn decrypt_nip04(&self, keypair: &Keypair) -> Result<String, Nip04Error> {
+        if self.kind() != 4 {
+            return Err(Nip04Error::NotADirectMessage);
+        }
+
+        let peer = if self.pubkey() == keypair.pubkey() {
+            self.tags()
+                .into_iter()
+                .filter(|tag| tag.count() >= 2 && tag.get_str(0) == Some("p"))
+                .find_map(|tag| tag.get_id(1))
+                .ok_or(Nip04Error::MissingRecipient)?
+        } else {
+            self.pubkey()
+        };
+
+        nip04::decrypt(keypair.secret_key(), peer, self.content())
+    }
+
     #[inline]
     pub fn rumor_giftwrap_id(&self) -> Option<&'a [u8; 32]> {
         unsafe {
@@ -321,6 +401,31 @@ impl<'a> Note<'a> {
             &*(ptr as *const [u8; 64])
         }
     }
+
+    /// Check that the note id matches its contents and that it was
+    /// signed by its pubkey. Notes from the database have already been
+    /// verified during ingestion, unless validation was skipped.
+    pub fn verify_with_bufsize(&self, bufsize: usize) -> bool {
+        let mut scratch: Vec<u8> = vec![0; bufsize];
+        unsafe {
+            bindings::ndb_note_verify(
+                secp::static_context(),
+                scratch.as_mut_ptr(),
+                scratch.len(),
+                self.as_ptr(),
+            ) != 0
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        // 1mb buffer, same as json()
+        self.verify_with_bufsize(1024usize * 1024usize)
+    }
+
+    /// The NIP-18 `q` tags of this note: quoted events and addresses
+    pub fn quotes(&self) -> Vec<Quote<'a>> {
+        self.tags().iter().filter_map(Quote::from_tag).collect()
+    }
 }
 
 impl Drop for Note<'_> {
@@ -420,6 +525,23 @@ impl<'a> NoteBuilder<'a> {
         Self::with_bufsize(default_bufsize).expect("OOM when creating NoteBuilder")
     }
 
+    /// Start a legacy NIP-04 DM (kind 4) from `sender` to `recipient`,
+    /// signed by the sender. Prefer NIP-17 gift wraps for new messages.
+    pub fn encrypted_dm(
+        sender: &'a Keypair,
+        recipient: &[u8; 32],
+        plaintext: &str,
+    ) -> Result<Self, Nip04Error> {
+        let content = nip04::encrypt(sender.secret_key(), recipient, plaintext)?;
+        Ok(NoteBuilder::new()
+            .kind(4)
+            .content(&content)
+            .start_tag()
+            .tag_str("p")
+            .tag_id(recipient)
+            .sign(sender.secret_key()))
+    }
+
     pub fn as_mut_ptr(&mut self) -> *mut bindings::ndb_builder {
         &mut self.builder as *mut bindings::ndb_builder
     }
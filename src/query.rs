@@ -1,4 +1,8 @@
-use crate::{bindings, Note, NoteKey, Transaction};
+use crate::util::address;
+use crate::util::nip09::Deletions;
+use crate::{bindings, Error, Filter, Note, NoteKey, Result, Transaction};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug)]
 pub struct QueryResult<'a> {
@@ -7,6 +11,34 @@ pub struct QueryResult<'a> {
     pub note_key: NoteKey,
 }
 
+/// Controls which notes [Ndb::query_with] leaves out of its results.
+///
+/// [Ndb::query_with]: crate::Ndb::query_with
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct QueryOptions {
+    pub(crate) old_versions: bool,
+    pub(crate) deleted: bool,
+}
+
+impl QueryOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Include replaceable and addressable notes that have been replaced
+    /// by a newer version
+    pub fn old_versions(mut self, include: bool) -> Self {
+        self.old_versions = include;
+        self
+    }
+
+    /// Include notes that their author deleted with a NIP-09 deletion
+    /// request
+    pub fn deleted(mut self, include: bool) -> Self {
+        self.deleted = include;
+        self
+    }
+}
 impl<'a> QueryResult<'a> {
     pub fn new(result: &bindings::ndb_query_result, txn: &'a Transaction) -> Self {
         QueryResult {
@@ -21,3 +53,218 @@ impl<'a> QueryResult<'a> {
         }
     }
 }
+
+/// Run a query straight against the indices, without hiding replaced or
+/// deleted notes.
+pub(crate) fn query_raw<'a>(
+    txn: &'a Transaction,
+    filters: &[Filter],
+    max_results: i32,
+) -> Result<Vec<QueryResult<'a>>> {
+    let mut ndb_filters: Vec<bindings::ndb_filter> = filters.iter().map(|a| a.data).collect();
+    let mut out: Vec<bindings::ndb_query_result> = vec![];
+    let mut returned: i32 = 0;
+    out.reserve_exact(max_results as usize);
+    let res = unsafe {
+        bindings::ndb_query(
+            txn.as_mut_ptr(),
+            ndb_filters.as_mut_ptr(),
+            ndb_filters.len() as i32,
+            out.as_mut_ptr(),
+            max_results,
+            &mut returned as *mut i32,
+        )
+    };
+    if res == 1 {
+        unsafe {
+            out.set_len(returned as usize);
+        };
+        Ok(out.iter().map(|r| QueryResult::new(r, txn)).collect())
+    } else {
+        Err(Error::QueryError)
+    }
+}
+
+/// Get a note by its id. Backs [Ndb::get_note_by_id], for code that only
+/// has a [Transaction].
+///
+/// [Ndb::get_note_by_id]: crate::Ndb::get_note_by_id
+pub(crate) fn note_by_id<'a>(txn: &'a Transaction, id: &[u8; 32]) -> Result<Note<'a>> {
+    let mut len: usize = 0;
+    let mut primkey: u64 = 0;
+
+    let note_ptr = unsafe {
+        bindings::ndb_get_note_by_id(txn.as_mut_ptr(), id.as_ptr(), &mut len, &mut primkey)
+    };
+
+    if note_ptr.is_null() {
+        // Handle null pointer (e.g., note not found or error occurred)
+        return Err(Error::NotFound);
+    }
+
+    Ok(Note::new_transactional(
+        note_ptr,
+        len,
+        NoteKey::new(primkey),
+        txn,
+    ))
+}
+
+/// Run `filter` without its limit, handing every matching note to `visit`
+/// instead of collecting it. The custom element goes last, so it only sees
+/// notes that match everything else. Fails if `filter` has no room left for
+/// that element.
+pub(crate) fn walk(txn: &Transaction, filter: &Filter, mut visit: impl FnMut(&Note)) -> Result<()> {
+    // SAFETY: the copy is dropped before `visit`
+    let walking = unsafe {
+        filter.copy_with_custom(false, |note| {
+            visit(&note);
+            false
+        })?
+    };
+
+    query_raw(txn, &[walking], 1)?;
+    Ok(())
+}
+
+/// [walk] each of `filters`, handing every note to `visit` once, even when
+/// more than one filter matches it
+pub(crate) fn walk_all(
+    txn: &Transaction,
+    filters: &[Filter],
+    mut visit: impl FnMut(&Note),
+) -> Result<()> {
+    // one filter can't match a note twice, so there's nothing to remember
+    let mut seen = (filters.len() > 1).then(HashSet::new);
+    for filter in filters {
+        walk(txn, filter, |note| {
+            if seen.as_mut().is_some_and(|seen| !seen.insert(*note.id())) {
+                return;
+            }
+            visit(note);
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Query with [QueryOptions] applied while nostrdb scans the indices, so
+/// replaced and deleted notes don't use up `max_results` or filter limits.
+/// A filter that has no room left for the custom element that does this is
+/// run as it is and checked afterwards, so hidden notes still count toward
+/// its limit.
+pub(crate) fn query<'a>(
+    txn: &'a Transaction,
+    filters: &[Filter],
+    max_results: i32,
+    options: QueryOptions,
+) -> Result<Vec<QueryResult<'a>>> {
+    if options.old_versions && options.deleted {
+        return query_raw(txn, filters, max_results);
+    }
+
+    let visibility = RefCell::new(Visibility::new(txn, options));
+    // SAFETY: the copies are dropped before `visibility`
+    let scanning: Result<Vec<Filter>> = filters
+        .iter()
+        .map(|filter| unsafe {
+            filter.copy_with_custom(true, |note| visibility.borrow_mut().keep(&note))
+        })
+        .collect();
+
+    let results = match scanning {
+        Ok(scanning) => query_raw(txn, &scanning, max_results),
+        Err(Error::Filter(_)) => query_raw(txn, filters, max_results).map(|mut results| {
+            let mut visibility = visibility.borrow_mut();
+            results.retain(|res| visibility.keep(&res.note));
+            results
+        }),
+        Err(err) => return Err(err),
+    };
+
+    visibility.into_inner().finish()?;
+    results
+}
+
+/// The address of a replaceable or addressable note
+type Address = (u32, [u8; 32], String);
+
+/// Decides note by note which notes [QueryOptions] leaves out, remembering
+/// the newest version of every address it has looked up.
+pub(crate) struct Visibility<'a> {
+    txn: &'a Transaction,
+    options: QueryOptions,
+    newest: HashMap<Address, Option<[u8; 32]>>,
+    deletions: Deletions,
+    error: Option<Error>,
+}
+
+impl<'a> Visibility<'a> {
+    pub(crate) fn new(txn: &'a Transaction, options: QueryOptions) -> Self {
+        Visibility {
+            txn,
+            options,
+            newest: HashMap::new(),
+            deletions: Deletions::default(),
+            error: None,
+        }
+    }
+
+    /// Whether to keep `note`. After an error every note is left out, and
+    /// the error comes back from [Visibility::finish].
+    pub(crate) fn keep(&mut self, note: &Note) -> bool {
+        if self.error.is_some() {
+            return false;
+        }
+
+        match self.is_visible(note) {
+            Ok(visible) => visible,
+            Err(err) => {
+                self.error = Some(err);
+                false
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<()> {
+        self.error.map_or(Ok(()), Err)
+    }
+
+    fn is_visible(&mut self, note: &Note) -> Result<bool> {
+        if !self.options.old_versions && !self.is_latest_version(note)? {
+            return Ok(false);
+        }
+
+        if !self.options.deleted && self.deletions.is_deleted(self.txn, note)? {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Compares against the newest version stored, even if it was deleted,
+    /// since that still replaces the older ones.
+    fn is_latest_version(&mut self, note: &Note) -> Result<bool> {
+        let Some(d_tag) = address::d_tag(note) else {
+            return Ok(true);
+        };
+
+        let key = (note.kind(), *note.pubkey(), d_tag);
+        if let Some(newest) = self.newest.get(&key) {
+            return Ok(newest.is_none_or(|id| id == *note.id()));
+        }
+
+        let mut filter = Filter::new()
+            .authors([note.pubkey()])
+            .kinds([note.kind() as u64]);
+        if address::is_addressable_kind(note.kind()) {
+            filter = filter.tags([key.2.as_str()], 'd');
+        }
+        let newest = query_raw(self.txn, &[filter.build()], 1)?
+            .first()
+            .map(|res| *res.note.id());
+        self.newest.insert(key, newest);
+
+        Ok(newest.is_none_or(|id| id == *note.id()))
+    }
+}
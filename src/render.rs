@@ -0,0 +1,366 @@
+//! Render note content [`Blocks`] into HTML or CommonMark.
+//!
+//! Text is always escaped for the output format, and URLs only become links
+//! if [`RenderHooks::url`] allows them, which by default means `http` and
+//! `https` urls.
+
+use crate::{Block, BlockType, Blocks, Mention, Ndb, Note, Transaction};
+
+/// Customize how mentions, urls and hashtags are rendered. Every method has
+/// a default, so implement only the ones you need.
+pub trait RenderHooks {
+    /// The name shown for a mentioned pubkey, without the leading `@`.
+    /// Defaults to an abbreviated bech32 string.
+    fn mention_name(&self, _pubkey: &[u8; 32]) -> Option<String> {
+        None
+    }
+
+    /// Where a mention should link to. `bech32` is the mention without its
+    /// `nostr:` prefix.
+    fn mention_url(&self, bech32: &str) -> Option<String> {
+        Some(format!("nostr:{bech32}"))
+    }
+
+    /// Where a url should link to. Return `None` to render it as text.
+    fn url(&self, url: &str) -> Option<String> {
+        if url.starts_with("https://") || url.starts_with("http://") {
+            Some(url.to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Where a hashtag should link to. Return `None` to render it as text.
+    fn hashtag_url(&self, _hashtag: &str) -> Option<String> {
+        None
+    }
+}
+
+/// The default [`RenderHooks`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultHooks;
+
+impl RenderHooks for DefaultHooks {}
+
+/// [`RenderHooks`] that show mentioned profiles by their display name or
+/// name, from nostrdb.
+#[derive(Debug)]
+pub struct ProfileNames<'a> {
+    ndb: &'a Ndb,
+    txn: &'a Transaction,
+}
+
+impl<'a> ProfileNames<'a> {
+    pub fn new(ndb: &'a Ndb, txn: &'a Transaction) -> Self {
+        ProfileNames { ndb, txn }
+    }
+}
+
+impl RenderHooks for ProfileNames<'_> {
+    fn mention_name(&self, pubkey: &[u8; 32]) -> Option<String> {
+        let record = self.ndb.get_profile_by_pubkey(self.txn, pubkey).ok()?;
+        let profile = record.record().profile()?;
+        profile
+            .display_name()
+            .filter(|name| !name.is_empty())
+            .or(profile.name())
+            .map(|name| name.to_owned())
+    }
+}
+
+/// Render a note's content blocks as HTML
+pub fn render_html(note: &Note, blocks: &Blocks, hooks: &impl RenderHooks) -> String {
+    render(note, blocks, hooks, &Html)
+}
+
+/// Render a note's content blocks as CommonMark
+pub fn render_markdown(note: &Note, blocks: &Blocks, hooks: &impl RenderHooks) -> String {
+    render(note, blocks, hooks, &Markdown)
+}
+
+trait Format {
+    fn text(&self, out: &mut String, text: &str);
+    fn link(&self, out: &mut String, class: &str, text: &str, href: &str);
+}
+
+struct Html;
+
+impl Format for Html {
+    fn text(&self, out: &mut String, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                out.push_str("<br>\n");
+            }
+            escape_html(out, line);
+        }
+    }
+
+    fn link(&self, out: &mut String, class: &str, text: &str, href: &str) {
+        out.push_str("<a class=\"");
+        out.push_str(class);
+        out.push_str("\" href=\"");
+        escape_html(out, href);
+        out.push_str("\">");
+        escape_html(out, text);
+        out.push_str("</a>");
+    }
+}
+
+struct Markdown;
+
+impl Format for Markdown {
+    fn text(&self, out: &mut String, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                out.push_str("\\\n");
+            }
+            escape_markdown(out, line);
+        }
+    }
+
+    fn link(&self, out: &mut String, _class: &str, text: &str, href: &str) {
+        out.push('[');
+        escape_markdown(out, text);
+        out.push_str("](<");
+        for c in href.chars() {
+            match c {
+                '<' => out.push_str("%3C"),
+                '>' => out.push_str("%3E"),
+                ' ' => out.push_str("%20"),
+                '\n' | '\r' => {}
+                c => out.push(c),
+            }
+        }
+        out.push_str(">)");
+    }
+}
+
+fn render(note: &Note, blocks: &Blocks, hooks: &impl RenderHooks, format: &impl Format) -> String {
+    let mut out = String::with_capacity(note.content().len() * 2);
+
+    for block in blocks.iter(note) {
+        match block.blocktype() {
+            BlockType::Text => format.text(&mut out, block.as_str()),
+
+            BlockType::Hashtag => {
+                let tag = format!("#{}", block.as_str());
+                match hooks.hashtag_url(block.as_str()) {
+                    Some(href) => format.link(&mut out, "hashtag", &tag, &href),
+                    None => format.text(&mut out, &tag),
+                }
+            }
+
+            BlockType::Url => match hooks.url(block.as_str()) {
+                Some(href) => format.link(&mut out, "url", block.as_str(), &href),
+                None => format.text(&mut out, block.as_str()),
+            },
+
+            BlockType::MentionBech32 => render_mention(&mut out, &block, hooks, format),
+
+            // legacy #[0] style mentions
+            BlockType::MentionIndex => {
+                let index = unsafe { (*block.as_ptr()).block.mention_index };
+                format.text(&mut out, &format!("#[{index}]"));
+            }
+
+            BlockType::Invoice => {
+                let invoice = block.as_str();
+                let href = format!("lightning:{invoice}");
+                format.link(&mut out, "invoice", invoice, &href);
+            }
+        }
+    }
+
+    out
+}
+
+fn render_mention(out: &mut String, block: &Block, hooks: &impl RenderHooks, format: &impl Format) {
+    let bech32 = block.as_str();
+    let pubkey = match block.as_mention() {
+        Some(Mention::Pubkey(npub)) => Some(npub.pubkey()),
+        Some(Mention::Profile(nprofile)) => Some(nprofile.pubkey()),
+        _ => None,
+    };
+
+    let text = match pubkey {
+        Some(pubkey) => {
+            let name = hooks
+                .mention_name(pubkey)
+                .unwrap_or_else(|| abbreviate(bech32));
+            format!("@{name}")
+        }
+        None => abbreviate(bech32),
+    };
+
+    match hooks.mention_url(bech32) {
+        Some(href) => format.link(out, "mention", &text, &href),
+        None => format.text(out, &text),
+    }
+}
+
+fn abbreviate(bech32: &str) -> String {
+    match bech32.find('1') {
+        Some(sep) if bech32.len() > sep + 12 => {
+            format!("{}…{}", &bech32[..sep + 9], &bech32[bech32.len() - 4..])
+        }
+        _ => bech32.to_owned(),
+    }
+}
+
+fn escape_html(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_markdown(out: &mut String, text: &str) {
+    for c in text.chars() {
+        if c.is_ascii_punctuation() {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const NOTE: &str = "[\"EVENT\",\"s\",{\"id\":\"d28ac02e277c3cf2744b562a414fd92d5fea554a737901364735bfe74577f304\",\"pubkey\":\"b5b1b5d2914daa2eda99af22ae828effe98730bf69dcca000fa37bfb9e395e32\",\"created_at\": 1703989205,\"kind\": 1,\"tags\": [],\"content\": \"#hashtags, are neat nostr:nprofile1qqsr9cvzwc652r4m83d86ykplrnm9dg5gwdvzzn8ameanlvut35wy3gpz3mhxue69uhhyetvv9ujuerpd46hxtnfduyu75sw https://github.com/damus-io\",\"sig\": \"07af3062616a17ef392769cadb170ac855c817c103e007c72374499bbadb2fe8917a0cc5b3fdc5aa5d56de086e128b3aeaa8868f6fe42a409767241b6a29cc94\"}]";
+
+    const NOTE_ID: &str = "d28ac02e277c3cf2744b562a414fd92d5fea554a737901364735bfe74577f304";
+
+    struct Hooks;
+
+    impl RenderHooks for Hooks {
+        fn mention_name(&self, _pubkey: &[u8; 32]) -> Option<String> {
+            Some("jb55".to_owned())
+        }
+
+        fn mention_url(&self, bech32: &str) -> Option<String> {
+            Some(format!("https://damus.io/{bech32}"))
+        }
+
+        fn hashtag_url(&self, hashtag: &str) -> Option<String> {
+            Some(format!("/t/{hashtag}"))
+        }
+    }
+
+    fn with_note(db: &str, f: impl FnOnce(&Ndb, &Transaction, &Note, &Blocks)) {
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let sub = ndb
+                .subscribe(&[Filter::new().kinds([1]).build()])
+                .expect("sub");
+            ndb.process_event(NOTE).expect("process ok");
+            for _ in 0..500 {
+                if !ndb.poll_for_notes(sub, 1).is_empty() {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let id: [u8; 32] = hex::decode(NOTE_ID).unwrap().try_into().unwrap();
+            let note = ndb.get_note_by_id(&txn, &id).expect("note");
+            let blocks = ndb
+                .get_blocks_by_key(&txn, note.key().unwrap())
+                .expect("blocks");
+
+            f(&ndb, &txn, &note, &blocks);
+        }
+
+        test_util::cleanup_db(db);
+    }
+
+    #[test]
+    fn render_html_works() {
+        with_note("target/testdbs/render_html", |ndb, txn, note, blocks| {
+            assert_eq!(
+                render_html(note, blocks, &DefaultHooks),
+                "#hashtags, are neat <a class=\"mention\" href=\"nostr:nprofile1qqsr9cvzwc652r4m83d86ykplrnm9dg5gwdvzzn8ameanlvut35wy3gpz3mhxue69uhhyetvv9ujuerpd46hxtnfduyu75sw\">@nprofile1qqsr9cvz…75sw</a> <a class=\"url\" href=\"https://github.com/damus-io\">https://github.com/damus-io</a>"
+            );
+
+            assert_eq!(
+                render_html(note, blocks, &Hooks),
+                "<a class=\"hashtag\" href=\"/t/hashtags\">#hashtags</a>, are neat <a class=\"mention\" href=\"https://damus.io/nprofile1qqsr9cvzwc652r4m83d86ykplrnm9dg5gwdvzzn8ameanlvut35wy3gpz3mhxue69uhhyetvv9ujuerpd46hxtnfduyu75sw\">@jb55</a> <a class=\"url\" href=\"https://github.com/damus-io\">https://github.com/damus-io</a>"
+            );
+
+            // nobody in the db, so we fall back to the abbreviated nprofile
+            assert_eq!(
+                render_html(note, blocks, &ProfileNames::new(ndb, txn)),
+                render_html(note, blocks, &DefaultHooks)
+            );
+        });
+    }
+
+    #[test]
+    fn render_markdown_works() {
+        with_note(
+            "target/testdbs/render_markdown",
+            |_ndb, _txn, note, blocks| {
+                assert_eq!(
+                render_markdown(note, blocks, &Hooks),
+                "[\\#hashtags](</t/hashtags>)\\, are neat [\\@jb55](<https://damus.io/nprofile1qqsr9cvzwc652r4m83d86ykplrnm9dg5gwdvzzn8ameanlvut35wy3gpz3mhxue69uhhyetvv9ujuerpd46hxtnfduyu75sw>) [https\\:\\/\\/github\\.com\\/damus\\-io](<https://github.com/damus-io>)"
+            );
+            },
+        );
+    }
+
+    #[test]
+    fn render_escapes_content() {
+        let db = "target/testdbs/render_escapes";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let seckey = [11; 32];
+            let profile = NoteBuilder::new()
+                .kind(0)
+                .content(r#"{"display_name":"<b>bob</b>"}"#)
+                .sign(&seckey)
+                .build()
+                .expect("profile");
+            let pubkey = *profile.pubkey();
+            let npub = Bech32::encode_npub(&pubkey);
+
+            let note = NoteBuilder::new()
+                .kind(1)
+                .content(&format!(
+                    "<script>alert('hi')</script>\nhey nostr:{npub} javascript:alert(1)"
+                ))
+                .sign(&seckey)
+                .build()
+                .expect("note");
+            test_util::ingest_notes(&ndb, &[profile, note.clone()]);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let note = ndb.get_note_by_id(&txn, note.id()).expect("note");
+            let blocks = ndb
+                .get_blocks_by_key(&txn, note.key().unwrap())
+                .expect("blocks");
+            let names = ProfileNames::new(&ndb, &txn);
+
+            assert_eq!(
+                render_html(&note, &blocks, &names),
+                format!("&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt;<br>\nhey <a class=\"mention\" href=\"nostr:{npub}\">@&lt;b&gt;bob&lt;/b&gt;</a> javascript:alert(1)")
+            );
+
+            assert_eq!(
+                render_markdown(&note, &blocks, &names),
+                format!("\\<script\\>alert\\(\\'hi\\'\\)\\<\\/script\\>\\\nhey [\\@\\<b\\>bob\\<\\/b\\>](<nostr:{npub}>) javascript\\:alert\\(1\\)")
+            );
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -0,0 +1,88 @@
+//! libsecp256k1 symbols that nostrdb links in but doesn't expose in its
+//! headers, so they aren't part of the generated bindings.
+
+use std::os::raw::{c_int, c_void};
+
+extern "C" {
+    /// A context that can be used for verification without allocating
+    pub static secp256k1_context_static: *const c_void;
+}
+
+/// The static context as the `void *` nostrdb expects
+pub(crate) fn static_context() -> *mut c_void {
+    unsafe { secp256k1_context_static as *mut c_void }
+}
+
+/// An opaque parsed public key, `secp256k1_pubkey`
+#[repr(C)]
+struct Pubkey([u8; 64]);
+
+type EcdhHashFn = unsafe extern "C" fn(
+    output: *mut u8,
+    x32: *const u8,
+    y32: *const u8,
+    data: *mut c_void,
+) -> c_int;
+
+extern "C" {
+    fn secp256k1_ec_pubkey_parse(
+        ctx: *const c_void,
+        pubkey: *mut Pubkey,
+        input: *const u8,
+        inputlen: usize,
+    ) -> c_int;
+
+    fn secp256k1_ecdh(
+        ctx: *const c_void,
+        output: *mut u8,
+        pubkey: *const Pubkey,
+        seckey: *const u8,
+        hashfp: Option<EcdhHashFn>,
+        data: *mut c_void,
+    ) -> c_int;
+}
+
+unsafe extern "C" fn copy_x(
+    output: *mut u8,
+    x32: *const u8,
+    _y32: *const u8,
+    _data: *mut c_void,
+) -> c_int {
+    std::ptr::copy_nonoverlapping(x32, output, 32);
+    1
+}
+
+/// The unhashed x coordinate of the ECDH shared point between `seckey` and
+/// the x-only `pubkey`. Returns `None` if either key is invalid.
+pub(crate) fn shared_x(seckey: &[u8; 32], pubkey: &[u8; 32]) -> Option<[u8; 32]> {
+    let mut compressed = [2u8; 33];
+    compressed[1..].copy_from_slice(pubkey);
+
+    let mut parsed = Pubkey([0; 64]);
+    let mut shared = [0u8; 32];
+    unsafe {
+        if secp256k1_ec_pubkey_parse(
+            static_context(),
+            &mut parsed,
+            compressed.as_ptr(),
+            compressed.len(),
+        ) == 0
+        {
+            return None;
+        }
+
+        if secp256k1_ecdh(
+            static_context(),
+            shared.as_mut_ptr(),
+            &parsed,
+            seckey.as_ptr(),
+            Some(copy_x),
+            std::ptr::null_mut(),
+        ) == 0
+        {
+            return None;
+        }
+    }
+
+    Some(shared)
+}
@@ -1,3 +1,4 @@
+use crate::{bech32, Filter, Ndb, Note};
 use std::fs;
 use std::path::Path;
 
@@ -7,3 +8,62 @@ pub fn cleanup_db(path: &str) {
     let _ = fs::remove_file(p.join("data.mdb"));
     let _ = fs::remove_file(p.join("lock.mdb"));
 }
+
+/// Ingest notes created with a [crate::NoteBuilder] and block until nostrdb
+/// has written all of them.
+#[allow(dead_code)]
+pub fn ingest_notes(ndb: &Ndb, notes: &[Note<'_>]) {
+    let filter = Filter::new().ids(notes.iter().map(|n| n.id())).build();
+    let sub = ndb.subscribe(&[filter]).expect("sub");
+
+    for note in notes {
+        let json = note.json().expect("note json");
+        ndb.process_event(&format!(r#"["EVENT","test",{json}]"#))
+            .expect("process ok");
+    }
+
+    let mut seen = 0;
+    for _ in 0..500 {
+        seen += ndb.poll_for_notes(sub, notes.len() as u32).len();
+        if seen >= notes.len() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let _ = ndb.clone().unsubscribe(sub);
+    assert_eq!(seen, notes.len(), "not all notes were ingested");
+}
+
+/// Build an unsigned mainnet bolt11 invoice for `msats` with a payment hash
+/// and a description hash. nostrdb doesn't check invoice signatures, so this
+/// is enough for tests.
+#[allow(dead_code)]
+pub fn bolt11_invoice(msats: u64, payment_hash: &[u8; 32], description_hash: &[u8; 32]) -> String {
+    fn push_bits(data: &mut Vec<u8>, value: u64, bits: u32) {
+        for i in (0..bits / 5).rev() {
+            data.push(((value >> (i * 5)) & 31) as u8);
+        }
+    }
+
+    fn push_field(data: &mut Vec<u8>, typ: u8, bytes: &[u8; 32]) {
+        data.push(typ);
+        push_bits(data, 52, 10);
+        data.extend(bech32::bytes_to_words(bytes));
+    }
+
+    let mut data = vec![];
+    push_bits(&mut data, 1700000000, 35);
+    push_field(&mut data, 1, payment_hash); // p
+    push_field(&mut data, 23, description_hash); // h
+    data.resize(data.len() + 104, 0); // signature + recovery id
+
+    // nostrdb only accepts short human readable parts, so use the largest
+    // multiplier that fits
+    let amount = [('m', 100_000_000), ('u', 100_000), ('n', 100)]
+        .iter()
+        .find(|(_, unit)| msats / unit * unit == msats)
+        .map(|(m, unit)| format!("{}{m}", msats / unit))
+        .unwrap_or_else(|| format!("{}p", msats * 10));
+    bech32::encode(&format!("lnbc{amount}"), &data)
+}
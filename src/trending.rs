@@ -0,0 +1,322 @@
+//! An in-memory index of recent hashtags, for asking what's trending. It is
+//! filled in by the writer thread through a subscription whose custom
+//! filter element indexes each new note and then rejects it, so nothing is
+//! ever queued for the subscription.
+
+use crate::block::BlockIter;
+use crate::query::walk;
+use crate::{bindings, BlockType, Filter, Ndb, Note, Result, Transaction};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::os::raw::c_int;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Hashtags are counted per minute, so `since` is rounded down to one
+const BUCKET_SECS: u64 = 60;
+
+/// The kinds whose hashtags are tracked: text notes, pictures, videos,
+/// comments and longform articles
+const HASHTAG_KINDS: [u64; 6] = [1, 20, 21, 22, 1111, 30023];
+
+/// A hashtag used in recent notes, from [Ndb::trending_hashtags]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TrendingHashtag {
+    /// The lowercased hashtag, without a `#`
+    pub hashtag: String,
+    /// How many notes used it
+    pub notes: u64,
+    /// How many different pubkeys used it
+    pub authors: u64,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    notes: u64,
+    authors: HashSet<[u8; 32]>,
+}
+
+#[derive(Debug)]
+struct HashtagIndex {
+    window: u64,
+    /// bucket start -> hashtag -> usage
+    buckets: BTreeMap<u64, HashMap<String, Bucket>>,
+    /// bucket start -> ids of the notes counted in it
+    counted: BTreeMap<u64, HashSet<[u8; 32]>>,
+}
+
+impl HashtagIndex {
+    fn add(&mut self, note: &Note, now: u64) {
+        let oldest = now.saturating_sub(self.window);
+        if note.created_at() < oldest {
+            return;
+        }
+
+        let hashtags = hashtags(note);
+        if hashtags.is_empty() {
+            return;
+        }
+
+        let start = note.created_at() / BUCKET_SECS * BUCKET_SECS;
+        if !self.counted.entry(start).or_default().insert(*note.id()) {
+            return;
+        }
+
+        let bucket = self.buckets.entry(start).or_default();
+        for hashtag in hashtags {
+            let usage = bucket.entry(hashtag).or_default();
+            usage.notes += 1;
+            usage.authors.insert(*note.pubkey());
+        }
+
+        // forget buckets that have left the window
+        let first = oldest / BUCKET_SECS * BUCKET_SECS;
+        self.buckets = self.buckets.split_off(&first);
+        self.counted = self.counted.split_off(&first);
+    }
+
+    fn trending(&self, since: u64, limit: usize) -> Vec<TrendingHashtag> {
+        let mut totals: HashMap<&str, (u64, HashSet<&[u8; 32]>)> = HashMap::new();
+        for bucket in self.buckets.range(since / BUCKET_SECS * BUCKET_SECS..) {
+            for (hashtag, usage) in bucket.1 {
+                let total = totals.entry(hashtag).or_default();
+                total.0 += usage.notes;
+                total.1.extend(usage.authors.iter());
+            }
+        }
+
+        let mut trending: Vec<TrendingHashtag> = totals
+            .into_iter()
+            .map(|(hashtag, (notes, authors))| TrendingHashtag {
+                hashtag: hashtag.to_owned(),
+                notes,
+                authors: authors.len() as u64,
+            })
+            .collect();
+
+        trending.sort_by(|a, b| {
+            b.notes
+                .cmp(&a.notes)
+                .then_with(|| b.authors.cmp(&a.authors))
+                .then_with(|| a.hashtag.cmp(&b.hashtag))
+        });
+        trending.truncate(limit);
+        trending
+    }
+}
+
+/// The distinct, normalized hashtags of a note, from its `t` tags and from
+/// `#hashtags` in its content. Most clients add a `t` tag for each hashtag
+/// in the content, but not all of them do.
+fn hashtags(note: &Note) -> Vec<String> {
+    let mut hashtags = vec![];
+    let mut add = |hashtag: &str| {
+        let hashtag = hashtag.trim_start_matches('#').to_lowercase();
+        if !hashtag.is_empty() && !hashtags.contains(&hashtag) {
+            hashtags.push(hashtag);
+        }
+    };
+
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_str(0) != Some("t") {
+            continue;
+        }
+
+        if let Some(hashtag) = tag.get_str(1) {
+            add(hashtag);
+        }
+    }
+
+    content_hashtags(note, &mut add);
+    hashtags
+}
+
+/// Parse the content for hashtags. The writer thread stores a note's blocks
+/// after subscriptions have seen it, so they can't be read back yet.
+fn content_hashtags(note: &Note, add: &mut impl FnMut(&str)) {
+    let content = note.content();
+    if !content.contains('#') {
+        return;
+    }
+
+    // the blocks take a few bytes per character at most, plus a header
+    let mut buf = vec![0u8; content.len() * 8 + 1024];
+    let mut blocks: *mut bindings::ndb_blocks = std::ptr::null_mut();
+    let ok = unsafe {
+        bindings::ndb_parse_content(
+            buf.as_mut_ptr(),
+            buf.len() as c_int,
+            note.content_ptr(),
+            content.len() as c_int,
+            &mut blocks,
+        )
+    };
+    if ok == 0 {
+        return;
+    }
+
+    // the blocks live in `buf`, so nothing needs freeing
+    let iter = BlockIter::new_owned(note.content_ptr(), blocks);
+    for block in iter {
+        if block.blocktype() == BlockType::Hashtag {
+            add(block.as_str());
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Keeps a [HashtagIndex] up to date
+#[derive(Debug)]
+pub(crate) struct HashtagTracker {
+    index: Arc<Mutex<HashtagIndex>>,
+    _filter: Filter,
+}
+
+impl HashtagTracker {
+    /// Subscribe to new notes, then index the ones already in the window.
+    /// Subscribing first means none are missed in between, and the index
+    /// only counts each note once.
+    pub(crate) fn start(ndb: &Ndb, window: Duration) -> Result<Self> {
+        let window = window.as_secs();
+        let index = Arc::new(Mutex::new(HashtagIndex {
+            window,
+            buckets: BTreeMap::new(),
+            counted: BTreeMap::new(),
+        }));
+
+        let writer_index = index.clone();
+        let filter = Filter::new()
+            .kinds(HASHTAG_KINDS)
+            .custom(move |note| {
+                writer_index.lock().unwrap().add(&note, now());
+                false
+            })
+            .build();
+        ndb.subscribe(std::slice::from_ref(&filter))?;
+
+        {
+            let txn = Transaction::new(ndb)?;
+            // the kind index is sorted by time, so this only walks the window
+            let since = Filter::new()
+                .kinds(HASHTAG_KINDS)
+                .since(now().saturating_sub(window))
+                .build();
+            let now = now();
+            walk(&txn, &since, |note| index.lock().unwrap().add(note, now))?;
+        }
+
+        Ok(HashtagTracker {
+            index,
+            _filter: filter,
+        })
+    }
+
+    pub(crate) fn trending(&self, since: u64, limit: usize) -> Vec<TrendingHashtag> {
+        self.index.lock().unwrap().trending(since, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::{test_util, NoteBuilder};
+
+    #[test]
+    fn trending_hashtags_work() {
+        let db = "target/testdbs/trending_hashtags";
+        test_util::cleanup_db(db);
+
+        let now = now();
+        let note = |seckey: &[u8; 32], created_at: u64, hashtags: &[&str]| {
+            let mut builder = NoteBuilder::new()
+                .kind(1)
+                .content("hello")
+                .created_at(created_at);
+            for hashtag in hashtags {
+                builder = builder.start_tag().tag_str("t").tag_str(hashtag);
+            }
+            builder.sign(seckey).build().expect("note")
+        };
+
+        let config = Config::new().set_trending_window(Duration::from_secs(24 * 60 * 60));
+
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    note(&[60; 32], now - 600, &["Nostr", "nostr"]),
+                    note(&[61; 32], now - 300, &["nostr", "rust"]),
+                    // too old for the window
+                    note(&[62; 32], now - 3 * 24 * 60 * 60, &["rust", "rust"]),
+                    // hashtags in the content count without a `t` tag
+                    NoteBuilder::new()
+                        .kind(1)
+                        .content("gm #Rust, #rust")
+                        .created_at(now - 200)
+                        .sign(&[63; 32])
+                        .build()
+                        .expect("note"),
+                ],
+            );
+
+            let trending = ndb
+                .trending_hashtags(now - 6 * 60 * 60, 10)
+                .expect("trending");
+            assert_eq!(
+                trending,
+                vec![
+                    TrendingHashtag {
+                        hashtag: "nostr".to_string(),
+                        notes: 2,
+                        authors: 2
+                    },
+                    TrendingHashtag {
+                        hashtag: "rust".to_string(),
+                        notes: 2,
+                        authors: 2
+                    },
+                ]
+            );
+
+            let recent = ndb.trending_hashtags(now - 400, 1).expect("trending");
+            assert_eq!(recent.len(), 1);
+            assert_eq!(recent[0].hashtag, "rust");
+            assert_eq!(recent[0].notes, 2);
+        }
+
+        // reopening picks the notes up from the database
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            let trending = ndb.trending_hashtags(now - 3600, 10).expect("trending");
+            assert_eq!(trending.len(), 2);
+            assert_eq!(trending[0].notes, 2);
+
+            // a note seen by both the subscription and the backfill only
+            // counts once
+            let mut index = HashtagIndex {
+                window: 3600,
+                buckets: BTreeMap::new(),
+                counted: BTreeMap::new(),
+            };
+            let twice = note(&[60; 32], now - 600, &["nostr"]);
+            index.add(&twice, now);
+            index.add(&twice, now);
+            assert_eq!(index.trending(now - 3600, 10)[0].notes, 1);
+
+            assert!(Ndb::new(&format!("{db}_untracked"), &Config::new())
+                .expect("ndb")
+                .trending_hashtags(now - 3600, 10)
+                .is_err());
+        }
+
+        test_util::cleanup_db(db);
+        test_util::cleanup_db(&format!("{db}_untracked"));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::Note;
+use std::fmt;
+
+/// The coordinate of an addressable (or replaceable) event, as found in
+/// `a` tags: `<kind>:<pubkey>:<d-tag>`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct NoteAddress<'a> {
+    pub kind: u32,
+    pub pubkey: [u8; 32],
+    pub identifier: &'a str,
+}
+
+impl<'a> NoteAddress<'a> {
+    pub fn new(kind: u32, pubkey: &[u8; 32], identifier: &'a str) -> Self {
+        NoteAddress {
+            kind,
+            pubkey: *pubkey,
+            identifier,
+        }
+    }
+
+    /// Parse a `<kind>:<pubkey>:<d-tag>` coordinate. The d-tag may be empty
+    /// and may itself contain colons.
+    pub fn parse(coord: &'a str) -> Option<Self> {
+        let mut parts = coord.splitn(3, ':');
+        let kind = parts.next()?.parse().ok()?;
+        let pubkey = hex_decode_32(parts.next()?)?;
+        let identifier = parts.next().unwrap_or("");
+
+        Some(NoteAddress {
+            kind,
+            pubkey,
+            identifier,
+        })
+    }
+}
+
+impl fmt::Display for NoteAddress<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.kind)?;
+        for byte in self.pubkey {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, ":{}", self.identifier)
+    }
+}
+
+/// Kinds where only the newest note per pubkey is kept
+pub(crate) fn is_replaceable_kind(kind: u32) -> bool {
+    kind == 0 || kind == 3 || (10000..20000).contains(&kind)
+}
+
+/// Kinds where only the newest note per pubkey and `d` tag is kept
+pub(crate) fn is_addressable_kind(kind: u32) -> bool {
+    (30000..40000).contains(&kind)
+}
+
+/// The `d` tag that, along with kind and pubkey, identifies a replaceable
+/// or addressable note. Replaceable notes always use an empty one. Returns
+/// `None` for regular notes.
+pub(crate) fn d_tag(note: &Note) -> Option<String> {
+    let kind = note.kind();
+    if is_replaceable_kind(kind) {
+        Some(String::new())
+    } else if is_addressable_kind(kind) {
+        let d_tag = note
+            .tags()
+            .iter()
+            .find(|tag| tag.count() >= 2 && tag.get_str(0) == Some("d"))
+            .and_then(|tag| tag.get_str(1).map(|d| d.to_owned()));
+        Some(d_tag.unwrap_or_default())
+    } else {
+        None
+    }
+}
+
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub(crate) fn hex_decode_32(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.as_bytes();
+    if hex.len() != 64 {
+        return None;
+    }
+
+    let mut out = [0u8; 32];
+    for (i, pair) in hex.chunks(2).enumerate() {
+        let hi = (pair[0] as char).to_digit(16)?;
+        let lo = (pair[1] as char).to_digit(16)?;
+        out[i] = (hi * 16 + lo) as u8;
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_address_roundtrip() {
+        let coord =
+            "30023:a695f6b60119d9521934a691347d9f78e8770b56da16bb255ee286ddf9fda919:my:article";
+        let addr = NoteAddress::parse(coord).expect("address");
+        assert_eq!(addr.kind, 30023);
+        assert_eq!(addr.pubkey[0], 0xa6);
+        assert_eq!(addr.identifier, "my:article");
+        assert_eq!(addr.to_string(), coord);
+
+        let replaceable = NoteAddress::parse(
+            "10002:a695f6b60119d9521934a691347d9f78e8770b56da16bb255ee286ddf9fda919:",
+        )
+        .expect("replaceable");
+        assert_eq!(replaceable.identifier, "");
+
+        assert!(NoteAddress::parse("30023:nothex:foo").is_none());
+        assert!(NoteAddress::parse(
+            "kind:a695f6b60119d9521934a691347d9f78e8770b56da16bb255ee286ddf9fda919:foo"
+        )
+        .is_none());
+    }
+}
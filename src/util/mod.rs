@@ -1 +1,14 @@
+pub mod address;
+pub mod nip02;
+pub mod nip04;
+pub mod nip09;
 pub mod nip10;
+pub mod nip17;
+pub mod nip18;
+pub mod nip22;
+pub mod nip44;
+pub mod nip51;
+pub mod nip57;
+pub mod nip59;
+pub mod thread;
+pub mod wot;
@@ -0,0 +1,167 @@
+use crate::{Note, Tag};
+
+/// The kind used for contact lists
+pub(crate) const CONTACTS_KIND: u32 = 3;
+
+/// An entry in a NIP-02 contact list (kind 3): a `p` tag with an optional
+/// relay hint and petname
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Follow<'a> {
+    /// The author of the contact list
+    pub follower: &'a [u8; 32],
+    pub followed: &'a [u8; 32],
+    pub relay: Option<&'a str>,
+    pub petname: Option<&'a str>,
+}
+
+impl<'a> Follow<'a> {
+    /// Parse a `p` tag from `follower`'s contact list
+    pub fn from_tag(follower: &'a [u8; 32], tag: Tag<'a>) -> Option<Self> {
+        if tag.count() < 2 || tag.get_str(0) != Some("p") {
+            return None;
+        }
+
+        Some(Follow {
+            follower,
+            followed: tag.get_id(1)?,
+            relay: tag.get_str(2).filter(|x| !x.is_empty()),
+            petname: tag.get_str(3).filter(|x| !x.is_empty()),
+        })
+    }
+}
+
+/// Everyone followed by a contact list, in order, without duplicates. Empty
+/// if the note isn't a contact list.
+pub fn follows<'a>(note: &Note<'a>) -> Vec<Follow<'a>> {
+    if note.kind() != CONTACTS_KIND {
+        return vec![];
+    }
+
+    let mut follows: Vec<Follow<'a>> = vec![];
+    for tag in note.tags() {
+        let Some(follow) = Follow::from_tag(note.pubkey(), tag) else {
+            continue;
+        };
+
+        if !follows.iter().any(|f| f.followed == follow.followed) {
+            follows.push(follow);
+        }
+    }
+
+    follows
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::{test_util, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn follow_graph_works() {
+        follow_graph_works_with("target/testdbs/nip02_follows", Config::new());
+        follow_graph_works_with(
+            "target/testdbs/nip02_follows_indexed",
+            Config::new().set_follower_index(true),
+        );
+    }
+
+    fn follow_graph_works_with(db: &str, config: Config) {
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &config).expect("ndb");
+            assert_eq!(ndb.subscription_count(), 0);
+            let alice = [70; 32];
+            let bob = [71; 32];
+            let carol = [72; 32];
+
+            let pubkey = |seckey: &[u8; 32]| {
+                *NoteBuilder::new()
+                    .kind(1)
+                    .content("")
+                    .sign(seckey)
+                    .build()
+                    .expect("note")
+                    .pubkey()
+            };
+            let (alice_pk, bob_pk, carol_pk) = (pubkey(&alice), pubkey(&bob), pubkey(&carol));
+
+            let contacts = |seckey: &[u8; 32], created_at: u64, follows: &[&[u8; 32]]| {
+                let mut builder = NoteBuilder::new()
+                    .kind(3)
+                    .content("")
+                    .created_at(created_at);
+                for followed in follows {
+                    builder = builder
+                        .start_tag()
+                        .tag_str("p")
+                        .tag_id(followed)
+                        .tag_str("wss://relay.example")
+                        .tag_str("pal");
+                }
+                builder.sign(seckey).build().expect("contacts")
+            };
+
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    contacts(&alice, 1, &[&bob_pk, &carol_pk, &bob_pk]),
+                    contacts(&bob, 1, &[&carol_pk]),
+                    // carol unfollows bob
+                    contacts(&carol, 1, &[&bob_pk]),
+                    contacts(&carol, 2, &[&alice_pk]),
+                ],
+            );
+
+            let txn = Transaction::new(&ndb).expect("txn");
+
+            let follows = ndb.follows(&txn, &alice_pk).expect("follows");
+            assert_eq!(follows.len(), 2);
+            assert_eq!(follows[0].follower, &alice_pk);
+            assert_eq!(follows[0].followed, &bob_pk);
+            assert_eq!(follows[0].relay, Some("wss://relay.example"));
+            assert_eq!(follows[0].petname, Some("pal"));
+            assert_eq!(follows[1].followed, &carol_pk);
+
+            let mut followers: Vec<[u8; 32]> = ndb
+                .followers(&txn, &carol_pk)
+                .expect("followers")
+                .iter()
+                .map(|f| *f.follower)
+                .collect();
+            followers.sort();
+            let mut expected = vec![alice_pk, bob_pk];
+            expected.sort();
+            assert_eq!(followers, expected);
+
+            let followers = ndb.followers(&txn, &bob_pk).expect("followers");
+            assert_eq!(followers.len(), 1);
+            assert_eq!(followers[0].follower, &alice_pk);
+            assert_eq!(followers[0].petname, Some("pal"));
+
+            assert!(ndb.follows(&txn, &[99; 32]).expect("follows").is_empty());
+            drop(followers);
+            drop(txn);
+
+            // carol follows bob again
+            test_util::ingest_notes(&ndb, &[contacts(&carol, 3, &[&bob_pk])]);
+            let txn = Transaction::new(&ndb).expect("txn");
+            let mut followers: Vec<[u8; 32]> = ndb
+                .followers(&txn, &bob_pk)
+                .expect("followers")
+                .iter()
+                .map(|f| *f.follower)
+                .collect();
+            followers.sort();
+            let mut expected = vec![alice_pk, carol_pk];
+            expected.sort();
+            assert_eq!(followers, expected);
+            assert!(ndb
+                .followers(&txn, &alice_pk)
+                .expect("followers")
+                .is_empty());
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
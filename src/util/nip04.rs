@@ -0,0 +1,114 @@
+//! Legacy NIP-04 encrypted DMs (kind 4): AES-256-CBC with the unhashed ECDH
+//! shared secret as the key, sent as `<base64 ciphertext>?iv=<base64 iv>`.
+//! Use [nip44](crate::nip44) for anything new.
+
+use crate::keypair::random_bytes;
+use crate::{secp, Nip04Error};
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+type Encryptor = cbc::Encryptor<aes::Aes256>;
+type Decryptor = cbc::Decryptor<aes::Aes256>;
+
+/// Encrypt `plaintext` from `sender_sec` to `recipient_pub` as NIP-04 DM
+/// content
+pub fn encrypt(
+    sender_sec: &[u8; 32],
+    recipient_pub: &[u8; 32],
+    plaintext: &str,
+) -> Result<String, Nip04Error> {
+    let key = secp::shared_x(sender_sec, recipient_pub).ok_or(Nip04Error::EcdhFailed)?;
+    let mut iv = [0u8; 16];
+    random_bytes(&mut iv);
+
+    let ciphertext = Encryptor::new(&key.into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+    Ok(format!(
+        "{}?iv={}",
+        BASE64.encode(ciphertext),
+        BASE64.encode(iv)
+    ))
+}
+
+/// Decrypt NIP-04 DM `content` sent between `recipient_sec` and
+/// `sender_pub`
+pub fn decrypt(
+    recipient_sec: &[u8; 32],
+    sender_pub: &[u8; 32],
+    content: &str,
+) -> Result<String, Nip04Error> {
+    let (ciphertext, iv) = content
+        .split_once("?iv=")
+        .ok_or(Nip04Error::InvalidPayload)?;
+    let ciphertext = BASE64
+        .decode(ciphertext)
+        .map_err(|_| Nip04Error::Base64Decode)?;
+    let iv: [u8; 16] = BASE64
+        .decode(iv)
+        .map_err(|_| Nip04Error::Base64Decode)?
+        .try_into()
+        .map_err(|_| Nip04Error::InvalidPayload)?;
+
+    let key = secp::shared_x(recipient_sec, sender_pub).ok_or(Nip04Error::EcdhFailed)?;
+    let plaintext = Decryptor::new(&key.into(), &iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+        .map_err(|_| Nip04Error::InvalidPadding)?;
+    String::from_utf8(plaintext).map_err(|_| Nip04Error::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Keypair, NoteBuilder};
+
+    #[test]
+    fn nip04_roundtrips() {
+        let alice = Keypair::from_secret(&[1; 32]).expect("alice");
+        let bob = Keypair::from_secret(&[2; 32]).expect("bob");
+        let carol = Keypair::from_secret(&[3; 32]).expect("carol");
+
+        let content = encrypt(alice.secret_key(), bob.pubkey(), "hi bob").expect("encrypt");
+        assert!(content.contains("?iv="));
+        assert_eq!(
+            decrypt(bob.secret_key(), alice.pubkey(), &content).unwrap(),
+            "hi bob"
+        );
+        assert_eq!(
+            decrypt(alice.secret_key(), bob.pubkey(), &content).unwrap(),
+            "hi bob"
+        );
+        assert!(decrypt(carol.secret_key(), alice.pubkey(), &content).is_err());
+        assert_eq!(
+            decrypt(bob.secret_key(), alice.pubkey(), "no iv"),
+            Err(Nip04Error::InvalidPayload)
+        );
+        assert_eq!(
+            decrypt(bob.secret_key(), alice.pubkey(), "!!!?iv=AAAA"),
+            Err(Nip04Error::Base64Decode)
+        );
+
+        // a DM sent with the builder can be read by both sides
+        let dm = NoteBuilder::encrypted_dm(&alice, bob.pubkey(), "see you at 5")
+            .expect("dm")
+            .build()
+            .expect("note");
+        assert_eq!(dm.kind(), 4);
+        assert_eq!(dm.pubkey(), alice.pubkey());
+        assert_eq!(dm.decrypt_nip04(&bob).unwrap(), "see you at 5");
+        assert_eq!(dm.decrypt_nip04(&alice).unwrap(), "see you at 5");
+        assert!(dm.decrypt_nip04(&carol).is_err());
+
+        let not_a_dm = NoteBuilder::new()
+            .kind(1)
+            .content("hello")
+            .sign(alice.secret_key())
+            .build()
+            .expect("note");
+        assert_eq!(
+            not_a_dm.decrypt_nip04(&alice),
+            Err(Nip04Error::NotADirectMessage)
+        );
+    }
+}
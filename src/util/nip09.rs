@@ -0,0 +1,388 @@
+use crate::query::{note_by_id, query_raw, walk};
+use crate::util::address;
+use crate::{Filter, Note, NoteAddress, Result, Transaction};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+/// The kind used for deletion requests
+const DELETION_KIND: u32 = 5;
+
+/// A NIP-09 deletion request (kind 5)
+#[derive(Debug)]
+pub struct Deletion<'a> {
+    id: &'a [u8; 32],
+    author: &'a [u8; 32],
+    created_at: u64,
+    ids: Vec<&'a [u8; 32]>,
+    addresses: Vec<NoteAddress<'a>>,
+    reason: &'a str,
+}
+
+impl<'a> Deletion<'a> {
+    /// Parse a deletion request. Returns `None` if the note isn't kind 5.
+    pub fn new(note: &Note<'a>) -> Option<Deletion<'a>> {
+        if note.kind() != DELETION_KIND {
+            return None;
+        }
+
+        let mut deletion = Deletion {
+            id: note.id(),
+            author: note.pubkey(),
+            created_at: note.created_at(),
+            ids: vec![],
+            addresses: vec![],
+            reason: note.content(),
+        };
+
+        for tag in note.tags() {
+            if tag.count() < 2 {
+                continue;
+            }
+
+            match tag.get_str(0) {
+                Some("e") => deletion.ids.extend(tag.get_id(1)),
+                Some("a") => deletion
+                    .addresses
+                    .extend(tag.get_str(1).and_then(NoteAddress::parse)),
+                _ => {}
+            }
+        }
+
+        Some(deletion)
+    }
+
+    /// The id of the deletion request itself
+    pub fn id(&self) -> &'a [u8; 32] {
+        self.id
+    }
+
+    /// The pubkey of whoever asked for the deletion. Only notes by this
+    /// pubkey are deleted.
+    pub fn author(&self) -> &'a [u8; 32] {
+        self.author
+    }
+
+    /// The note ids from `e` tags
+    pub fn ids(&self) -> &[&'a [u8; 32]] {
+        &self.ids
+    }
+
+    /// The coordinates from `a` tags. Every version of these notes up to
+    /// the deletion's `created_at` is deleted.
+    pub fn addresses(&self) -> &[NoteAddress<'a>] {
+        &self.addresses
+    }
+
+    /// The optional reason given in the content
+    pub fn reason(&self) -> &'a str {
+        self.reason
+    }
+
+    /// Whether this deletion request has been ingested, which is what makes
+    /// its targets count as deleted.
+    pub fn is_applied(&self, txn: &Transaction) -> Result<bool> {
+        let filter = Filter::new().ids([self.id]).build();
+        Ok(!query_raw(txn, &[filter], 1)?.is_empty())
+    }
+
+    /// The notes in the database that this deletion request deletes. Notes
+    /// by anyone other than [Deletion::author] are never included.
+    pub fn deleted_notes<'t>(&self, txn: &'t Transaction) -> Result<Vec<Note<'t>>> {
+        if !self.is_applied(txn)? {
+            return Ok(vec![]);
+        }
+
+        let mut ids = vec![];
+
+        if !self.ids.is_empty() {
+            let filter = Filter::new()
+                .ids(self.ids.iter().copied())
+                .authors([self.author])
+                .build();
+            walk(txn, &filter, |note| {
+                if note.kind() != DELETION_KIND {
+                    ids.push(*note.id());
+                }
+            })?;
+        }
+
+        for address in &self.addresses {
+            if address.pubkey != *self.author {
+                continue;
+            }
+
+            let mut filter = Filter::new()
+                .authors([&address.pubkey])
+                .kinds([address.kind as u64])
+                // until is exclusive when scanning the indices
+                .until(self.created_at + 1);
+            if address::is_addressable_kind(address.kind) {
+                filter = filter.tags([address.identifier], 'd');
+            }
+
+            walk(txn, &filter.build(), |note| ids.push(*note.id()))?;
+        }
+
+        let mut notes = Vec::with_capacity(ids.len());
+        for id in ids {
+            notes.push(note_by_id(txn, &id)?);
+        }
+
+        Ok(notes)
+    }
+}
+
+/// Find the deletion request that deletes `note`, if any. Deletion requests
+/// only count when they come from the note's author, and can't themselves
+/// be deleted.
+pub(crate) fn find_deletion<'a>(txn: &'a Transaction, note: &Note) -> Result<Option<Deletion<'a>>> {
+    if note.kind() == DELETION_KIND {
+        return Ok(None);
+    }
+
+    let filter = Filter::new()
+        .authors([note.pubkey()])
+        .kinds([DELETION_KIND as u64])
+        .event(note.id())
+        .build();
+    if let Some(res) = query_raw(txn, &[filter], 1)?.into_iter().next() {
+        return Ok(Deletion::new(&res.note));
+    }
+
+    let Some(d_tag) = address::d_tag(note) else {
+        return Ok(None);
+    };
+
+    let coord = NoteAddress::new(note.kind(), note.pubkey(), &d_tag).to_string();
+    let filter = Filter::new()
+        .authors([note.pubkey()])
+        .kinds([DELETION_KIND as u64])
+        .tags([coord.as_str()], 'a')
+        .since(note.created_at())
+        .build();
+
+    Ok(query_raw(txn, &[filter], 1)?
+        .into_iter()
+        .next()
+        .and_then(|res| Deletion::new(&res.note)))
+}
+
+/// Which notes are deleted, for checking lots of notes at once. The first
+/// note by an author reads all of that author's deletion requests, and
+/// every later check against them is a lookup.
+#[derive(Default)]
+pub(crate) struct Deletions {
+    authors: HashMap<[u8; 32], AuthorDeletions>,
+}
+
+#[derive(Default)]
+struct AuthorDeletions {
+    ids: HashSet<[u8; 32]>,
+    /// The newest deletion of each `(kind, d tag)` address
+    addresses: HashMap<(u32, String), u64>,
+}
+
+impl Deletions {
+    /// Same as [find_deletion] finding something
+    pub(crate) fn is_deleted(&mut self, txn: &Transaction, note: &Note) -> Result<bool> {
+        if note.kind() == DELETION_KIND {
+            return Ok(false);
+        }
+
+        let deleted = match self.authors.entry(*note.pubkey()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(AuthorDeletions::load(txn, note.pubkey())?),
+        };
+
+        if deleted.ids.contains(note.id()) {
+            return Ok(true);
+        }
+
+        let Some(d_tag) = address::d_tag(note) else {
+            return Ok(false);
+        };
+
+        Ok(deleted
+            .addresses
+            .get(&(note.kind(), d_tag))
+            .is_some_and(|deleted_at| *deleted_at >= note.created_at()))
+    }
+}
+
+impl AuthorDeletions {
+    fn load(txn: &Transaction, author: &[u8; 32]) -> Result<Self> {
+        let mut deleted = AuthorDeletions::default();
+        let filter = Filter::new()
+            .authors([author])
+            .kinds([DELETION_KIND as u64])
+            .build();
+
+        walk(txn, &filter, |note| {
+            let Some(deletion) = Deletion::new(note) else {
+                return;
+            };
+
+            deleted.ids.extend(deletion.ids().iter().copied());
+            for address in deletion.addresses() {
+                if address.pubkey != *author {
+                    continue;
+                }
+
+                let key = (address.kind, address.identifier.to_owned());
+                let deleted_at = deleted.addresses.entry(key).or_default();
+                *deleted_at = (*deleted_at).max(deletion.created_at);
+            }
+        })?;
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::test_util;
+    use crate::{Ndb, NoteBuilder, QueryOptions};
+
+    #[test]
+    fn deletions_are_applied() {
+        let db = "target/testdbs/nip09_deletions";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let alice = [11; 32];
+            let bob = [12; 32];
+
+            let note = |seckey: &[u8; 32], kind: u32, created_at: u64, content: &str| {
+                NoteBuilder::new()
+                    .kind(kind)
+                    .content(content)
+                    .created_at(created_at)
+                    .sign(seckey)
+                    .build()
+                    .expect("note")
+            };
+            let article = |created_at: u64| {
+                NoteBuilder::new()
+                    .kind(30023)
+                    .content("article")
+                    .created_at(created_at)
+                    .start_tag()
+                    .tag_str("d")
+                    .tag_str("post")
+                    .sign(&alice)
+                    .build()
+                    .expect("article")
+            };
+
+            let deleted = note(&alice, 1, 1, "oops");
+            let kept = note(&alice, 1, 2, "fine");
+            let article_v1 = article(3);
+            let article_v2 = article(4);
+            let article_v3 = article(10);
+            let coord = NoteAddress::new(30023, article_v1.pubkey(), "post").to_string();
+
+            let deletion = NoteBuilder::new()
+                .kind(5)
+                .content("mistake")
+                .created_at(5)
+                .start_tag()
+                .tag_str("e")
+                .tag_id(deleted.id())
+                .start_tag()
+                .tag_str("a")
+                .tag_str(&coord)
+                .sign(&alice)
+                .build()
+                .expect("deletion");
+
+            // bob can't delete alice's notes
+            let forged = NoteBuilder::new()
+                .kind(5)
+                .content("")
+                .created_at(5)
+                .start_tag()
+                .tag_str("e")
+                .tag_id(kept.id())
+                .sign(&bob)
+                .build()
+                .expect("forged");
+
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    deleted.clone(),
+                    kept.clone(),
+                    article_v1.clone(),
+                    article_v2.clone(),
+                    article_v3.clone(),
+                    deletion.clone(),
+                    forged.clone(),
+                ],
+            );
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let get = |id: &[u8; 32]| ndb.get_note_by_id(&txn, id).expect("note");
+
+            let is_deleted = |id: &[u8; 32]| {
+                let note = get(id);
+                let found = note.is_deleted(&txn).expect("lookup");
+                let mut deletions = Deletions::default();
+                assert_eq!(deletions.is_deleted(&txn, &note).expect("lookup"), found);
+                found
+            };
+            assert!(is_deleted(deleted.id()));
+            assert!(!is_deleted(kept.id()));
+            assert!(is_deleted(article_v1.id()));
+            assert!(is_deleted(article_v2.id()));
+            assert!(!is_deleted(article_v3.id()));
+            assert!(!is_deleted(deletion.id()));
+            assert_eq!(
+                get(deleted.id())
+                    .find_deletion(&txn)
+                    .expect("lookup")
+                    .map(|d| *d.id()),
+                Some(*deletion.id())
+            );
+
+            let stored = get(deletion.id());
+            let parsed = Deletion::new(&stored).expect("deletion");
+            assert_eq!(parsed.reason(), "mistake");
+            assert!(parsed.is_applied(&txn).expect("applied"));
+            let mut ids: Vec<[u8; 32]> = parsed
+                .deleted_notes(&txn)
+                .expect("deleted notes")
+                .iter()
+                .map(|n| *n.id())
+                .collect();
+            ids.sort();
+            let mut expected = vec![*deleted.id(), *article_v1.id(), *article_v2.id()];
+            expected.sort();
+            assert_eq!(ids, expected);
+
+            let stored = get(forged.id());
+            let forged = Deletion::new(&stored).expect("forged");
+            assert!(forged.deleted_notes(&txn).expect("none").is_empty());
+
+            let filter = Filter::new().kinds([1]).build();
+            let visible = ndb
+                .query(&txn, std::slice::from_ref(&filter), 10)
+                .expect("query");
+            assert_eq!(visible.len(), 1);
+            assert_eq!(visible[0].note.id(), kept.id());
+
+            let options = QueryOptions::new().deleted(true);
+            let all = ndb.query_with(&txn, &[filter], 10, options).expect("query");
+            assert_eq!(all.len(), 2);
+
+            assert!(matches!(
+                ndb.get_addressable(&txn, 30023, article_v1.pubkey(), "post"),
+                Ok(note) if note.id() == article_v3.id()
+            ));
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -0,0 +1,343 @@
+//! One-to-one private messages: NIP-17 chat and file messages, which
+//! arrive as rumors unwrapped from giftwraps, and legacy NIP-04 DMs
+//! (kind 4).
+
+use crate::query::walk_all;
+use crate::util::nip09::Deletions;
+use crate::{Filter, Ndb, Note, Result, Transaction};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/// The kind used for NIP-04 encrypted DMs
+pub(crate) const LEGACY_DM_KIND: u32 = 4;
+
+/// The kinds of NIP-17 chat and file messages
+const CHAT_KINDS: [u32; 2] = [14, 15];
+
+const DM_KINDS: [u64; 3] = [LEGACY_DM_KIND as u64, 14, 15];
+
+/// A private message between us and a peer
+#[derive(Debug, Clone)]
+pub struct DirectMessage<'a> {
+    pub note: Note<'a>,
+    /// The other side of the conversation. This is us for notes to self.
+    pub peer: [u8; 32],
+    /// Whether we sent it
+    pub outgoing: bool,
+}
+
+impl<'a> DirectMessage<'a> {
+    /// Whether this is a NIP-04 DM, whose content is still encrypted. Read
+    /// it with [Note::decrypt_nip04].
+    pub fn is_legacy(&self) -> bool {
+        self.note.kind() == LEGACY_DM_KIND
+    }
+
+    /// The giftwrap a NIP-17 message was unwrapped from
+    pub fn giftwrap_id(&self) -> Option<&'a [u8; 32]> {
+        self.note.rumor_giftwrap_id()
+    }
+}
+
+/// A conversation with a peer, from [Ndb::dm_conversations]
+#[derive(Debug, Clone)]
+pub struct DmConversation<'a> {
+    pub peer: [u8; 32],
+    pub latest: DirectMessage<'a>,
+    /// How many messages there are in both directions
+    pub messages: u64,
+}
+
+/// The peer of a private message to or from `me`, or `None` if it isn't
+/// one. NIP-17 messages must be rumors unwrapped by one of the two sides,
+/// and messages to a group of more than one peer are left out.
+fn peer(note: &Note, me: &[u8; 32]) -> Option<[u8; 32]> {
+    let mut others: Vec<[u8; 32]> = vec![];
+    let mut to_me = false;
+    for tag in note.tags() {
+        if tag.count() < 2 || tag.get_str(0) != Some("p") {
+            continue;
+        }
+        let Some(pubkey) = tag.get_id(1) else {
+            continue;
+        };
+        if pubkey == me {
+            to_me = true;
+        } else if !others.contains(pubkey) {
+            others.push(*pubkey);
+        }
+    }
+
+    let peer = if note.pubkey() == me {
+        match others.as_slice() {
+            [] if to_me => *me,
+            [peer] => *peer,
+            _ => return None,
+        }
+    } else if to_me && others.is_empty() {
+        *note.pubkey()
+    } else if to_me && note.kind() == LEGACY_DM_KIND {
+        // kind 4 DMs may also p tag mentioned pubkeys
+        *note.pubkey()
+    } else {
+        return None;
+    };
+
+    if CHAT_KINDS.contains(&note.kind()) {
+        let receiver = note.rumor_receiver_pubkey()?;
+        if receiver != me && receiver != &peer {
+            return None;
+        }
+    }
+
+    Some(peer)
+}
+
+/// A message found while walking the indexes
+struct Found {
+    id: [u8; 32],
+    created_at: u64,
+    peer: [u8; 32],
+}
+
+/// Our messages matching `filters`, leaving out deleted ones
+fn find(txn: &Transaction, me: &[u8; 32], filters: &[Filter]) -> Result<Vec<Found>> {
+    let mut deletions = Deletions::default();
+    let mut found = vec![];
+    let mut error = None;
+    walk_all(txn, filters, |note| {
+        if error.is_some() {
+            return;
+        }
+        let Some(peer) = peer(note, me) else {
+            return;
+        };
+
+        match deletions.is_deleted(txn, note) {
+            Ok(true) => {}
+            Ok(false) => found.push(Found {
+                id: *note.id(),
+                created_at: note.created_at(),
+                peer,
+            }),
+            Err(err) => error = Some(err),
+        }
+    })?;
+
+    error.map_or(Ok(found), Err)
+}
+
+fn message<'a>(
+    ndb: &Ndb,
+    txn: &'a Transaction,
+    me: &[u8; 32],
+    id: &[u8; 32],
+    peer: [u8; 32],
+) -> Result<DirectMessage<'a>> {
+    let note = ndb.get_note_by_id(txn, id)?;
+    let outgoing = note.pubkey() == me;
+    Ok(DirectMessage {
+        note,
+        peer,
+        outgoing,
+    })
+}
+
+pub(crate) fn conversations<'a>(
+    ndb: &Ndb,
+    txn: &'a Transaction,
+    me: &[u8; 32],
+) -> Result<Vec<DmConversation<'a>>> {
+    let sent = Filter::new().kinds(DM_KINDS).authors([me]).build();
+    let received = Filter::new().kinds(DM_KINDS).pubkey([me]).build();
+
+    // peer -> (latest timestamp, latest id, messages)
+    let mut latest: HashMap<[u8; 32], (u64, [u8; 32], u64)> = HashMap::new();
+    for Found {
+        id,
+        created_at,
+        peer,
+    } in find(txn, me, &[sent, received])?
+    {
+        match latest.entry(peer) {
+            Entry::Vacant(entry) => {
+                entry.insert((created_at, id, 1));
+            }
+            Entry::Occupied(mut entry) => {
+                let convo = entry.get_mut();
+                convo.2 += 1;
+                if (created_at, id) > (convo.0, convo.1) {
+                    convo.0 = created_at;
+                    convo.1 = id;
+                }
+            }
+        }
+    }
+
+    let mut conversations = vec![];
+    for (peer, (_, id, messages)) in latest {
+        conversations.push(DmConversation {
+            peer,
+            latest: message(ndb, txn, me, &id, peer)?,
+            messages,
+        });
+    }
+
+    conversations.sort_by(|a, b| {
+        b.latest
+            .note
+            .created_at()
+            .cmp(&a.latest.note.created_at())
+            .then_with(|| a.peer.cmp(&b.peer))
+    });
+    Ok(conversations)
+}
+
+pub(crate) fn messages<'a>(
+    ndb: &Ndb,
+    txn: &'a Transaction,
+    me: &[u8; 32],
+    peer: &[u8; 32],
+) -> Result<Vec<DirectMessage<'a>>> {
+    let sent = Filter::new()
+        .kinds(DM_KINDS)
+        .authors([me])
+        .pubkey([peer])
+        .build();
+    let received = Filter::new()
+        .kinds(DM_KINDS)
+        .authors([peer])
+        .pubkey([me])
+        .build();
+
+    let mut found = find(txn, me, &[sent, received])?;
+    found.retain(|message| message.peer == *peer);
+    found.sort_by_key(|message| (message.created_at, message.id));
+
+    found
+        .into_iter()
+        .map(|found| message(ndb, txn, me, &found.id, found.peer))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::{test_util, GiftWrap, Keypair, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn dm_conversations_work() {
+        let db = "target/testdbs/nip17_dms";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let me = Keypair::from_secret(&[10; 32]).expect("me");
+            let alice = Keypair::from_secret(&[11; 32]).expect("alice");
+            let bob = Keypair::from_secret(&[12; 32]).expect("bob");
+            let carol = Keypair::from_secret(&[13; 32]).expect("carol");
+            assert!(ndb.add_key(me.secret_key()));
+
+            let chat = |from: &Keypair, to: &[&Keypair], created_at: u64, content: &str| {
+                let mut rumor = NoteBuilder::new()
+                    .kind(14)
+                    .content(content)
+                    .created_at(created_at);
+                for recipient in to {
+                    rumor = rumor.start_tag().tag_str("p").tag_id(recipient.pubkey());
+                }
+                let rumor = rumor.build().expect("rumor");
+                // the sender also wraps a copy for themselves
+                let mut wraps = vec![];
+                for recipient in to.iter().copied().chain([from]) {
+                    wraps.push(
+                        GiftWrap::seal_and_wrap(&rumor, from, recipient.pubkey())
+                            .expect("wrap")
+                            .into_wrap(),
+                    );
+                }
+                wraps
+            };
+            let legacy = |from: &Keypair, to: &Keypair, created_at: u64| {
+                NoteBuilder::new()
+                    .kind(4)
+                    .content("ciphertext?iv=abc")
+                    .created_at(created_at)
+                    .start_tag()
+                    .tag_str("p")
+                    .tag_id(to.pubkey())
+                    .sign(from.secret_key())
+                    .build()
+                    .expect("dm")
+            };
+
+            let mut notes = vec![];
+            notes.extend(chat(&alice, &[&me], 10, "hi"));
+            notes.extend(chat(&me, &[&alice], 20, "hello alice"));
+            notes.extend(chat(&bob, &[&me], 15, "yo"));
+            notes.extend(chat(&me, &[&me], 5, "note to self"));
+            // group chats and messages between others are left out
+            notes.extend(chat(&bob, &[&me, &alice], 30, "group"));
+            notes.extend(chat(&bob, &[&alice], 40, "not for me"));
+            notes.push(legacy(&carol, &me, 25));
+            notes.push(legacy(&me, &alice, 12));
+            notes.push(legacy(&bob, &alice, 50));
+            // deleted messages are left out
+            let unsent = legacy(&bob, &me, 16);
+            notes.push(
+                NoteBuilder::new()
+                    .kind(5)
+                    .content("")
+                    .created_at(17)
+                    .start_tag()
+                    .tag_str("e")
+                    .tag_id(unsent.id())
+                    .sign(bob.secret_key())
+                    .build()
+                    .expect("deletion"),
+            );
+            notes.push(unsent);
+            test_util::ingest_notes(&ndb, &notes);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let conversations = ndb.dm_conversations(&txn, me.pubkey()).expect("convos");
+            let summary: Vec<([u8; 32], u64, u64)> = conversations
+                .iter()
+                .map(|c| (c.peer, c.latest.note.created_at(), c.messages))
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    (*carol.pubkey(), 25, 1),
+                    (*alice.pubkey(), 20, 3),
+                    (*bob.pubkey(), 15, 1),
+                    (*me.pubkey(), 5, 1),
+                ]
+            );
+            assert!(conversations[0].latest.is_legacy());
+            assert!(!conversations[0].latest.outgoing);
+            assert!(conversations[1].latest.outgoing);
+            assert!(conversations[1].latest.giftwrap_id().is_some());
+
+            let messages = ndb
+                .dm_messages(&txn, me.pubkey(), alice.pubkey())
+                .expect("messages");
+            let history: Vec<(u64, bool, bool)> = messages
+                .iter()
+                .map(|m| (m.note.created_at(), m.outgoing, m.is_legacy()))
+                .collect();
+            assert_eq!(
+                history,
+                vec![(10, false, false), (12, true, true), (20, true, false)]
+            );
+            assert_eq!(messages[0].note.content(), "hi");
+
+            assert!(ndb
+                .dm_messages(&txn, me.pubkey(), &[99; 32])
+                .expect("messages")
+                .is_empty());
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{Note, NoteAddress, Tag};
+
+/// A quoted event from a NIP-18 `q` tag
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Quote<'a> {
+    Event {
+        id: &'a [u8; 32],
+        relay: Option<&'a str>,
+        pubkey: Option<&'a [u8; 32]>,
+    },
+
+    Address {
+        address: NoteAddress<'a>,
+        relay: Option<&'a str>,
+    },
+}
+
+impl<'a> Quote<'a> {
+    pub fn from_tag(tag: Tag<'a>) -> Option<Self> {
+        if tag.count() < 2 || tag.get_str(0) != Some("q") {
+            return None;
+        }
+
+        let relay = tag.get_str(2).filter(|x| !x.is_empty());
+
+        if let Some(id) = tag.get_id(1) {
+            return Some(Quote::Event {
+                id,
+                relay,
+                pubkey: tag.get_id(3),
+            });
+        }
+
+        let address = NoteAddress::parse(tag.get_str(1)?)?;
+        Some(Quote::Address { address, relay })
+    }
+}
+
+/// A kind 6 repost or kind 16 generic repost
+#[derive(Debug)]
+pub struct Repost<'a> {
+    id: Option<&'a [u8; 32]>,
+    address: Option<NoteAddress<'a>>,
+    relay: Option<&'a str>,
+    pubkey: Option<&'a [u8; 32]>,
+    kind: Option<u32>,
+    embedded: Option<Note<'static>>,
+}
+
+impl<'a> Repost<'a> {
+    /// Parse a repost. Returns `None` if the note isn't a repost or doesn't
+    /// say what it is reposting.
+    pub fn new(note: &Note<'a>) -> Option<Repost<'a>> {
+        let kind = note.kind();
+        if kind != 6 && kind != 16 {
+            return None;
+        }
+
+        let mut repost = Repost {
+            id: None,
+            address: None,
+            relay: None,
+            pubkey: None,
+            kind: if kind == 6 { Some(1) } else { None },
+            embedded: None,
+        };
+
+        for tag in note.tags() {
+            if tag.count() < 2 {
+                continue;
+            }
+
+            match tag.get_str(0) {
+                Some("e") if repost.id.is_none() => {
+                    repost.id = tag.get_id(1);
+                    repost.relay = repost
+                        .relay
+                        .or_else(|| tag.get_str(2).filter(|x| !x.is_empty()));
+                }
+                Some("a") if repost.address.is_none() => {
+                    repost.address = tag.get_str(1).and_then(NoteAddress::parse);
+                    repost.relay = repost
+                        .relay
+                        .or_else(|| tag.get_str(2).filter(|x| !x.is_empty()));
+                }
+                Some("p") if repost.pubkey.is_none() => repost.pubkey = tag.get_id(1),
+                Some("k") if repost.kind.is_none() => {
+                    repost.kind = tag.get_str(1).and_then(|k| k.parse().ok())
+                }
+                _ => {}
+            }
+        }
+
+        if repost.id.is_none() && repost.address.is_none() {
+            return None;
+        }
+
+        repost.embedded = embedded_note(note.content(), repost.id);
+        Some(repost)
+    }
+
+    /// The id of the reposted note
+    pub fn target_id(&self) -> Option<&'a [u8; 32]> {
+        self.id
+    }
+
+    /// The coordinate of a reposted addressable event (kind 16 only)
+    pub fn target_address(&self) -> Option<&NoteAddress<'a>> {
+        self.address.as_ref()
+    }
+
+    pub fn relay(&self) -> Option<&'a str> {
+        self.relay
+    }
+
+    /// The author of the reposted note, from the `p` tag
+    pub fn target_pubkey(&self) -> Option<&'a [u8; 32]> {
+        self.pubkey
+    }
+
+    /// The kind of the reposted note. Always 1 for kind 6 reposts.
+    pub fn target_kind(&self) -> Option<u32> {
+        self.kind
+    }
+
+    /// The reposted note from the repost content, if it was included and
+    /// has a valid id and signature.
+    pub fn embedded(&self) -> Option<&Note<'static>> {
+        self.embedded.as_ref()
+    }
+}
+
+fn embedded_note(content: &str, id: Option<&[u8; 32]>) -> Option<Note<'static>> {
+    if !content.trim_start().starts_with('{') {
+        return None;
+    }
+
+    let note = Note::from_json(content).ok()?;
+    if id.is_some_and(|id| id != note.id()) {
+        return None;
+    }
+
+    if !note.verify() {
+        return None;
+    }
+
+    Some(note)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const SECKEY: [u8; 32] = [4; 32];
+
+    fn reposted() -> Note<'static> {
+        NoteBuilder::new()
+            .kind(1)
+            .content("gm")
+            .created_at(1700000000)
+            .sign(&SECKEY)
+            .build()
+            .expect("note")
+    }
+
+    #[test]
+    fn repost_embedded_note_works() {
+        let target = reposted();
+        let json = target.json().expect("json");
+
+        let repost = NoteBuilder::new()
+            .kind(6)
+            .content(&json)
+            .start_tag()
+            .tag_str("e")
+            .tag_id(target.id())
+            .tag_str("wss://relay.damus.io")
+            .start_tag()
+            .tag_str("p")
+            .tag_id(target.pubkey())
+            .sign(&SECKEY)
+            .build()
+            .expect("repost");
+
+        let parsed = Repost::new(&repost).expect("parsed");
+        assert_eq!(parsed.target_id(), Some(target.id()));
+        assert_eq!(parsed.target_pubkey(), Some(target.pubkey()));
+        assert_eq!(parsed.relay(), Some("wss://relay.damus.io"));
+        assert_eq!(parsed.target_kind(), Some(1));
+
+        let embedded = parsed.embedded().expect("embedded");
+        assert_eq!(embedded.id(), target.id());
+        assert_eq!(embedded.content(), "gm");
+
+        // tampered content is rejected
+        let tampered = json.replace("\"gm\"", "\"gn\"");
+        let repost = NoteBuilder::new()
+            .kind(6)
+            .content(&tampered)
+            .start_tag()
+            .tag_str("e")
+            .tag_id(target.id())
+            .sign(&SECKEY)
+            .build()
+            .expect("repost");
+
+        let parsed = Repost::new(&repost).expect("parsed");
+        assert_eq!(parsed.target_id(), Some(target.id()));
+        assert!(parsed.embedded().is_none());
+
+        assert!(Repost::new(&target).is_none());
+    }
+
+    #[test]
+    fn quotes_work() {
+        let quoted = reposted();
+        let coord = "30023:a695f6b60119d9521934a691347d9f78e8770b56da16bb255ee286ddf9fda919:ipsum";
+
+        let note = NoteBuilder::new()
+            .kind(1)
+            .content("look at this")
+            .start_tag()
+            .tag_str("q")
+            .tag_id(quoted.id())
+            .tag_str("wss://nos.lol")
+            .tag_id(quoted.pubkey())
+            .start_tag()
+            .tag_str("q")
+            .tag_str(coord)
+            .start_tag()
+            .tag_str("q")
+            .tag_str("garbage")
+            .sign(&SECKEY)
+            .build()
+            .expect("note");
+
+        let quotes = note.quotes();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(
+            quotes[0],
+            Quote::Event {
+                id: quoted.id(),
+                relay: Some("wss://nos.lol"),
+                pubkey: Some(quoted.pubkey()),
+            }
+        );
+        assert_eq!(
+            quotes[1],
+            Quote::Address {
+                address: NoteAddress::parse(coord).unwrap(),
+                relay: None,
+            }
+        );
+    }
+}
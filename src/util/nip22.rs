@@ -0,0 +1,358 @@
+use crate::{Filter, FilterBuilder, Tag, Tags};
+
+/// The kind used for NIP-22 comments
+const COMMENT_KIND: u64 = 1111;
+
+/// What a NIP-22 comment scope points at
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CommentTarget<'a> {
+    /// `E`/`e` tags
+    Event {
+        id: &'a [u8; 32],
+        relay: Option<&'a str>,
+        pubkey: Option<&'a [u8; 32]>,
+    },
+
+    /// `A`/`a` tags, in the form `<kind>:<pubkey>:<d-tag>`
+    Address {
+        address: &'a str,
+        relay: Option<&'a str>,
+    },
+
+    /// `I`/`i` tags, such as urls or podcast guids
+    External { id: &'a str, hint: Option<&'a str> },
+}
+
+/// A root or parent scope of a comment, along with its `K`/`k` kind and
+/// `P`/`p` author tags
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CommentScope<'a> {
+    pub target: CommentTarget<'a>,
+    pub kind: Option<&'a str>,
+    pub author: Option<&'a [u8; 32]>,
+}
+
+/// Parsed NIP-22 comment tags. Uppercase tags describe the root scope,
+/// lowercase tags describe the parent being replied to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NoteComment<'a> {
+    root: Option<CommentScope<'a>>,
+    parent: Option<CommentScope<'a>>,
+}
+
+#[derive(Default)]
+struct ScopeParts<'a> {
+    target: Option<CommentTarget<'a>>,
+    kind: Option<&'a str>,
+    author: Option<&'a [u8; 32]>,
+}
+
+impl<'a> ScopeParts<'a> {
+    fn scope(self) -> Option<CommentScope<'a>> {
+        Some(CommentScope {
+            target: self.target?,
+            kind: self.kind,
+            author: self.author,
+        })
+    }
+}
+
+impl<'a> NoteComment<'a> {
+    pub fn new(tags: Tags<'a>) -> NoteComment<'a> {
+        let mut root = ScopeParts::default();
+        let mut parent = ScopeParts::default();
+
+        for tag in tags {
+            if tag.count() < 2 {
+                continue;
+            }
+
+            let Some(name) = tag.get_str(0) else {
+                continue;
+            };
+
+            let (scope, name) = match name {
+                "E" | "A" | "I" | "K" | "P" => (&mut root, name),
+                "e" | "a" | "i" | "k" | "p" => (&mut parent, name),
+                _ => continue,
+            };
+
+            match name {
+                "E" | "e" | "A" | "a" | "I" | "i" => {
+                    if scope.target.is_none() {
+                        scope.target = tag_to_comment_target(&tag);
+                    }
+                }
+                "K" | "k" => {
+                    if scope.kind.is_none() {
+                        scope.kind = tag.get_str(1);
+                    }
+                }
+                _ => {
+                    if scope.author.is_none() {
+                        scope.author = tag.get_id(1);
+                    }
+                }
+            }
+        }
+
+        NoteComment {
+            root: root.scope(),
+            parent: parent.scope(),
+        }
+    }
+
+    pub fn root(self) -> Option<CommentScope<'a>> {
+        self.root
+    }
+
+    pub fn parent(self) -> Option<CommentScope<'a>> {
+        self.parent
+    }
+
+    /// Is this comment directly on the root, rather than a reply to
+    /// another comment?
+    pub fn is_top_level(&self) -> bool {
+        match (&self.root, &self.parent) {
+            (Some(root), Some(parent)) => root.target == parent.target,
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+}
+
+fn tag_to_comment_target<'a>(tag: &Tag<'a>) -> Option<CommentTarget<'a>> {
+    let hint = tag.get_str(2).filter(|x| !x.is_empty());
+
+    match tag.get_str(0)? {
+        "E" | "e" => Some(CommentTarget::Event {
+            id: tag.get_id(1)?,
+            relay: hint,
+            pubkey: tag.get_id(3),
+        }),
+        "A" | "a" => Some(CommentTarget::Address {
+            address: tag.get_str(1)?,
+            relay: hint,
+        }),
+        "I" | "i" => Some(CommentTarget::External {
+            id: tag.get_str(1)?,
+            hint,
+        }),
+        _ => None,
+    }
+}
+
+impl CommentTarget<'_> {
+    /// A filter matching every comment under this root scope, at any depth
+    pub fn comments_filter(&self) -> FilterBuilder {
+        let tag = match self {
+            CommentTarget::Event { .. } => 'E',
+            CommentTarget::Address { .. } => 'A',
+            CommentTarget::External { .. } => 'I',
+        };
+        self.filter_on(tag)
+    }
+
+    /// A filter matching comments that directly reply to this scope
+    pub fn direct_comments_filter(&self) -> FilterBuilder {
+        let tag = match self {
+            CommentTarget::Event { .. } => 'e',
+            CommentTarget::Address { .. } => 'a',
+            CommentTarget::External { .. } => 'i',
+        };
+        self.filter_on(tag)
+    }
+
+    fn filter_on(&self, tag: char) -> FilterBuilder {
+        let mut builder = Filter::new().kinds([COMMENT_KIND]);
+        builder.start_tag_field(tag).unwrap();
+        match self {
+            CommentTarget::Event { id, .. } => builder.add_id_element(id).unwrap(),
+            CommentTarget::Address { address: s, .. } | CommentTarget::External { id: s, .. } => {
+                builder.add_str_element(s).unwrap()
+            }
+        }
+        builder.end_field();
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const SECKEY: [u8; 32] = [3; 32];
+
+    #[test]
+    fn nip22_parses_scopes() {
+        let root_id = [0x11; 32];
+        let root_author = [0x22; 32];
+        let parent_id = [0x33; 32];
+
+        let note = NoteBuilder::new()
+            .kind(1111)
+            .content("nice")
+            .start_tag()
+            .tag_str("E")
+            .tag_id(&root_id)
+            .tag_str("wss://relay.example.com")
+            .tag_id(&root_author)
+            .start_tag()
+            .tag_str("K")
+            .tag_str("1")
+            .start_tag()
+            .tag_str("P")
+            .tag_id(&root_author)
+            .start_tag()
+            .tag_str("e")
+            .tag_id(&parent_id)
+            .tag_str("")
+            .start_tag()
+            .tag_str("k")
+            .tag_str("1111")
+            .sign(&SECKEY)
+            .build()
+            .expect("note");
+
+        let comment = NoteComment::new(note.tags());
+        let root = comment.root().expect("root");
+        assert_eq!(
+            root.target,
+            CommentTarget::Event {
+                id: &root_id,
+                relay: Some("wss://relay.example.com"),
+                pubkey: Some(&root_author),
+            }
+        );
+        assert_eq!(root.kind, Some("1"));
+        assert_eq!(root.author, Some(&root_author));
+
+        let parent = comment.parent().expect("parent");
+        assert_eq!(
+            parent.target,
+            CommentTarget::Event {
+                id: &parent_id,
+                relay: None,
+                pubkey: None,
+            }
+        );
+        assert_eq!(parent.kind, Some("1111"));
+        assert!(!comment.is_top_level());
+
+        let external = NoteBuilder::new()
+            .kind(1111)
+            .content("great episode")
+            .start_tag()
+            .tag_str("I")
+            .tag_str("podcast:item:guid:d98d189b")
+            .start_tag()
+            .tag_str("K")
+            .tag_str("podcast:item:guid")
+            .start_tag()
+            .tag_str("i")
+            .tag_str("podcast:item:guid:d98d189b")
+            .start_tag()
+            .tag_str("k")
+            .tag_str("podcast:item:guid")
+            .sign(&SECKEY)
+            .build()
+            .expect("note");
+
+        let comment = NoteComment::new(external.tags());
+        assert_eq!(
+            comment.root().unwrap().target,
+            CommentTarget::External {
+                id: "podcast:item:guid:d98d189b",
+                hint: None,
+            }
+        );
+        assert!(comment.is_top_level());
+    }
+
+    #[test]
+    fn nip22_comments_filter_works() {
+        let db = "target/testdbs/nip22_comments_filter";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let address =
+                "30023:a695f6b60119d9521934a691347d9f78e8770b56da16bb255ee286ddf9fda919:ipsum";
+
+            let top = NoteBuilder::new()
+                .kind(1111)
+                .content("top")
+                .created_at(1)
+                .start_tag()
+                .tag_str("A")
+                .tag_str(address)
+                .start_tag()
+                .tag_str("a")
+                .tag_str(address)
+                .sign(&SECKEY)
+                .build()
+                .expect("top");
+
+            let nested = NoteBuilder::new()
+                .kind(1111)
+                .content("nested")
+                .created_at(2)
+                .start_tag()
+                .tag_str("A")
+                .tag_str(address)
+                .start_tag()
+                .tag_str("e")
+                .tag_id(top.id())
+                .sign(&SECKEY)
+                .build()
+                .expect("nested");
+
+            let unrelated = NoteBuilder::new()
+                .kind(1)
+                .content("unrelated")
+                .created_at(3)
+                .start_tag()
+                .tag_str("a")
+                .tag_str(address)
+                .sign(&SECKEY)
+                .build()
+                .expect("unrelated");
+
+            test_util::ingest_notes(&ndb, &[top.clone(), nested.clone(), unrelated]);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let root = CommentTarget::Address {
+                address,
+                relay: None,
+            };
+
+            let all = ndb
+                .query(&txn, &[root.comments_filter().build()], 10)
+                .expect("query");
+            let mut ids: Vec<&[u8; 32]> = all.iter().map(|r| r.note.id()).collect();
+            ids.sort();
+            let mut expected = vec![top.id(), nested.id()];
+            expected.sort();
+            assert_eq!(ids, expected);
+
+            let direct = ndb
+                .query(&txn, &[root.direct_comments_filter().build()], 10)
+                .expect("query");
+            assert_eq!(direct.len(), 1);
+            assert_eq!(direct[0].note.id(), top.id());
+
+            let parent = CommentTarget::Event {
+                id: top.id(),
+                relay: None,
+                pubkey: None,
+            };
+            let replies = ndb
+                .query(&txn, &[parent.direct_comments_filter().build()], 10)
+                .expect("query");
+            assert_eq!(replies.len(), 1);
+            assert_eq!(replies[0].note.id(), nested.id());
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -0,0 +1,169 @@
+//! NIP-44 (version 2) encrypted payloads, as used by giftwraps, private
+//! list items and DMs.
+
+use crate::{bindings, secp, Nip44Error};
+use std::os::raw::{c_char, c_uchar};
+
+fn check(rc: bindings::ndb_decrypt_result) -> Result<(), Nip44Error> {
+    Err(match rc {
+        bindings::ndb_decrypt_result_NIP44_OK => return Ok(()),
+        bindings::ndb_decrypt_result_NIP44_ERR_UNSUPPORTED_ENCODING => {
+            Nip44Error::UnsupportedEncoding
+        }
+        bindings::ndb_decrypt_result_NIP44_ERR_INVALID_PAYLOAD => Nip44Error::InvalidPayload,
+        bindings::ndb_decrypt_result_NIP44_ERR_BASE64_DECODE => Nip44Error::Base64Decode,
+        bindings::ndb_decrypt_result_NIP44_ERR_SECKEY_VERIFY_FAILED => {
+            Nip44Error::SeckeyVerifyFailed
+        }
+        bindings::ndb_decrypt_result_NIP44_ERR_PUBKEY_PARSE_FAILED => Nip44Error::PubkeyParseFailed,
+        bindings::ndb_decrypt_result_NIP44_ERR_ECDH_FAILED => Nip44Error::EcdhFailed,
+        bindings::ndb_decrypt_result_NIP44_ERR_FILL_RANDOM_FAILED => Nip44Error::FillRandomFailed,
+        bindings::ndb_decrypt_result_NIP44_ERR_INVALID_MAC => Nip44Error::InvalidMac,
+        bindings::ndb_decrypt_result_NIP44_ERR_INVALID_PADDING => Nip44Error::InvalidPadding,
+        bindings::ndb_decrypt_result_NIP44_ERR_BUFFER_TOO_SMALL => Nip44Error::BufferTooSmall,
+        code => Nip44Error::Unknown(code.into()),
+    })
+}
+
+/// Decrypt a NIP-44 `payload` sent by `sender_pub` to us, with our
+/// `recipient_sec`
+pub fn decrypt(
+    recipient_sec: &[u8; 32],
+    sender_pub: &[u8; 32],
+    payload: &str,
+) -> Result<String, Nip44Error> {
+    let payload_len = i32::try_from(payload.len()).map_err(|_| Nip44Error::InvalidPayload)?;
+    let mut buf = vec![0u8; payload.len() + 64];
+    let mut decrypted: *mut c_uchar = std::ptr::null_mut();
+    let mut decrypted_len: u16 = 0;
+
+    check(unsafe {
+        bindings::nip44_decrypt(
+            secp::static_context(),
+            sender_pub.as_ptr(),
+            recipient_sec.as_ptr(),
+            payload.as_ptr() as *const c_char,
+            payload_len,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut decrypted,
+            &mut decrypted_len,
+        )
+    })?;
+    if decrypted.is_null() {
+        return Err(Nip44Error::InvalidPayload);
+    }
+
+    let plaintext = unsafe { std::slice::from_raw_parts(decrypted, decrypted_len as usize) };
+    String::from_utf8(plaintext.to_vec()).map_err(|_| Nip44Error::InvalidUtf8)
+}
+
+/// The longest plaintext [encrypt] accepts. NIP-44 allows up to 65535
+/// bytes, but nostrdb's encrypter keeps the padded length in 16 bits, which
+/// overflows once padding rounds up to 65536.
+pub const MAX_PLAINTEXT_LEN: usize = 32768;
+
+/// Encrypt `plaintext` from `sender_sec` to `recipient_pub` as a base64
+/// NIP-44 payload. The plaintext must be 1 to [MAX_PLAINTEXT_LEN] bytes.
+pub fn encrypt(
+    sender_sec: &[u8; 32],
+    recipient_pub: &[u8; 32],
+    plaintext: &str,
+) -> Result<String, Nip44Error> {
+    if plaintext.is_empty() || plaintext.len() > MAX_PLAINTEXT_LEN {
+        return Err(Nip44Error::InvalidPlaintextLength);
+    }
+    // the padded message, then its base64 encoding
+    let raw = 99 + 2 * plaintext.len();
+    let mut buf = vec![0u8; raw * 3];
+    let mut out: *mut c_char = std::ptr::null_mut();
+    let mut out_len: isize = 0;
+
+    check(unsafe {
+        bindings::nip44_encrypt(
+            secp::static_context(),
+            sender_sec.as_ptr(),
+            recipient_pub.as_ptr(),
+            plaintext.as_ptr(),
+            plaintext.len() as u16,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut out,
+            &mut out_len,
+        )
+    })?;
+    if out.is_null() {
+        return Err(Nip44Error::BufferTooSmall);
+    }
+
+    let payload = unsafe { std::slice::from_raw_parts(out as *const u8, out_len as usize) };
+    String::from_utf8(payload.to_vec()).map_err(|_| Nip44Error::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoteBuilder;
+
+    fn pubkey(seckey: &[u8; 32]) -> [u8; 32] {
+        *NoteBuilder::new()
+            .kind(1)
+            .content("")
+            .sign(seckey)
+            .build()
+            .expect("note")
+            .pubkey()
+    }
+
+    #[test]
+    fn nip44_roundtrips() {
+        let alice = [1; 32];
+        let bob = [2; 32];
+        let (alice_pk, bob_pk) = (pubkey(&alice), pubkey(&bob));
+
+        let payload = encrypt(&alice, &bob_pk, "hello bob 👋").expect("encrypt");
+        assert_ne!(payload, encrypt(&alice, &bob_pk, "hello bob 👋").unwrap());
+        assert_eq!(decrypt(&bob, &alice_pk, &payload).unwrap(), "hello bob 👋");
+        // both sides share the conversation key
+        assert_eq!(decrypt(&alice, &bob_pk, &payload).unwrap(), "hello bob 👋");
+
+        let long = "x".repeat(MAX_PLAINTEXT_LEN + 1);
+        assert_eq!(
+            encrypt(&alice, &bob_pk, &long),
+            Err(Nip44Error::InvalidPlaintextLength)
+        );
+        assert_eq!(
+            encrypt(&alice, &bob_pk, ""),
+            Err(Nip44Error::InvalidPlaintextLength)
+        );
+        let large = "x".repeat(MAX_PLAINTEXT_LEN);
+        let payload = encrypt(&alice, &bob_pk, &large).expect("encrypt");
+        assert_eq!(decrypt(&bob, &alice_pk, &payload).unwrap(), large);
+    }
+
+    #[test]
+    fn nip44_errors() {
+        let alice = [1; 32];
+        let bob = [2; 32];
+        let carol = [3; 32];
+        let (alice_pk, bob_pk) = (pubkey(&alice), pubkey(&bob));
+        let payload = encrypt(&alice, &bob_pk, "secret").expect("encrypt");
+
+        assert_eq!(
+            decrypt(&carol, &alice_pk, &payload),
+            Err(Nip44Error::InvalidMac)
+        );
+        assert_eq!(
+            decrypt(&bob, &alice_pk, &format!("#{}", &payload[1..])),
+            Err(Nip44Error::UnsupportedEncoding)
+        );
+        assert_eq!(
+            decrypt(&bob, &alice_pk, "short"),
+            Err(Nip44Error::InvalidPayload)
+        );
+        assert_eq!(
+            encrypt(&[0; 32], &bob_pk, "secret"),
+            Err(Nip44Error::SeckeyVerifyFailed)
+        );
+    }
+}
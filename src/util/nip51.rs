@@ -0,0 +1,686 @@
+use crate::export::json_string;
+use crate::util::address::{self, hex_decode_32, hex_encode};
+use crate::util::{nip04, nip44};
+use crate::{Filter, Note, NoteAddress, NoteBuilder, Result, Tag};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The kind used for mute lists
+pub(crate) const MUTE_LIST_KIND: u32 = 10000;
+
+/// Parse the private items of a list: a JSON array of tags, encrypted by
+/// the list's author to themselves. That's NIP-44, or NIP-04 in lists from
+/// older clients, whose content has an `?iv=`. nostrdb's note parser does
+/// the JSON, so the tags are wrapped up as a note.
+pub(crate) fn private_tags(note: &Note, seckey: &[u8; 32]) -> Option<Note<'static>> {
+    let content = note.content();
+    if content.is_empty() {
+        return None;
+    }
+
+    let tags = if content.contains("?iv=") {
+        nip04::decrypt(seckey, note.pubkey(), content).ok()?
+    } else {
+        nip44::decrypt(seckey, note.pubkey(), content).ok()?
+    };
+    let zeros = "0".repeat(64);
+    let json = format!(
+        r#"{{"id":"{zeros}","pubkey":"{zeros}","created_at":0,"kind":{},"tags":{tags},"content":"","sig":"{zeros}{zeros}"}}"#,
+        note.kind()
+    );
+    Note::from_json(&json).ok()
+}
+
+/// A NIP-51 mute list (kind 10000): pubkeys, threads, hashtags and words
+/// whose notes we don't want to see. Use [MuteList::filter] to leave them
+/// out of queries and subscriptions.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct MuteList {
+    pubkeys: HashSet<[u8; 32]>,
+    threads: HashSet<[u8; 32]>,
+    hashtags: HashSet<String>,
+    words: Vec<String>,
+}
+
+impl MuteList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a mute list. With the author's `seckey`, the encrypted private
+    /// entries are included too. Returns `None` if the note isn't kind
+    /// 10000.
+    pub fn from_note(note: &Note, seckey: Option<&[u8; 32]>) -> Option<Self> {
+        if note.kind() != MUTE_LIST_KIND {
+            return None;
+        }
+
+        let mut mutes = MuteList::new();
+        mutes.add_tags(note);
+        if let Some(private) = seckey.and_then(|seckey| private_tags(note, seckey)) {
+            mutes.add_tags(&private);
+        }
+
+        Some(mutes)
+    }
+
+    fn add_tags(&mut self, note: &Note) {
+        for tag in note.tags() {
+            if tag.count() < 2 {
+                continue;
+            }
+
+            match tag.get_str(0) {
+                Some("p") => self.pubkeys.extend(tag.get_id(1).copied()),
+                Some("e") => self.threads.extend(tag.get_id(1).copied()),
+                Some("t") => {
+                    if let Some(hashtag) = tag.get_str(1) {
+                        self.add_hashtag(hashtag);
+                    }
+                }
+                Some("word") => {
+                    if let Some(word) = tag.get_str(1) {
+                        self.add_word(word);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn add_hashtag(&mut self, hashtag: &str) {
+        let hashtag = hashtag.trim_start_matches('#').to_lowercase();
+        if !hashtag.is_empty() {
+            self.hashtags.insert(hashtag);
+        }
+    }
+
+    fn add_word(&mut self, word: &str) {
+        let word = word.to_lowercase();
+        if !word.is_empty() && !self.words.contains(&word) {
+            self.words.push(word);
+        }
+    }
+
+    /// Mute notes by this pubkey
+    pub fn pubkey(mut self, pubkey: &[u8; 32]) -> Self {
+        self.pubkeys.insert(*pubkey);
+        self
+    }
+
+    /// Mute this note and the replies to it
+    pub fn thread(mut self, id: &[u8; 32]) -> Self {
+        self.threads.insert(*id);
+        self
+    }
+
+    /// Mute notes with this hashtag, ignoring case
+    pub fn hashtag(mut self, hashtag: &str) -> Self {
+        self.add_hashtag(hashtag);
+        self
+    }
+
+    /// Mute notes containing this word, ignoring case
+    pub fn word(mut self, word: &str) -> Self {
+        self.add_word(word);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pubkeys.is_empty()
+            && self.threads.is_empty()
+            && self.hashtags.is_empty()
+            && self.words.is_empty()
+    }
+
+    /// Whether the note is by a muted pubkey, in a muted thread, or uses a
+    /// muted hashtag or word
+    pub fn is_muted(&self, note: &Note) -> bool {
+        if self.pubkeys.contains(note.pubkey()) || self.threads.contains(note.id()) {
+            return true;
+        }
+
+        for tag in note.tags() {
+            if tag.count() < 2 {
+                continue;
+            }
+
+            let muted = match tag.get_str(0) {
+                Some("e") => tag.get_id(1).is_some_and(|id| self.threads.contains(id)),
+                Some("t") => tag
+                    .get_str(1)
+                    .is_some_and(|t| self.hashtags.contains(&t.to_lowercase())),
+                _ => false,
+            };
+            if muted {
+                return true;
+            }
+        }
+
+        if self.words.is_empty() {
+            return false;
+        }
+        let content = note.content().to_lowercase();
+        self.words.iter().any(|word| content.contains(word))
+    }
+
+    /// A copy of `filter` that leaves out muted notes. Works with both
+    /// [crate::Ndb::query] and [crate::Ndb::subscribe], and muted notes
+    /// don't count towards the limit. Keep the filter around for as long as
+    /// the subscription.
+    ///
+    /// A custom element `filter` already has keeps working. Fails if
+    /// `filter` has no room left for another field.
+    pub fn filter(&self, filter: &Filter) -> Result<Filter> {
+        let mutes = Arc::new(self.clone());
+        // SAFETY: the closure owns everything it uses
+        unsafe { filter.copy_with_custom(true, move |note| !mutes.is_muted(&note)) }
+    }
+}
+
+/// Tags that describe a list rather than being items in it
+const LIST_METADATA_TAGS: [&str; 5] = ["d", "title", "image", "description", "alt"];
+
+/// An entry in a [List]: a tag like `["p", <pubkey>, <relay>]` or
+/// `["t", "nostr"]`, with ids as hex
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ListItem {
+    values: Vec<String>,
+}
+
+impl ListItem {
+    /// An item from its tag values, eg. `["relay", "wss://relay.example"]`.
+    /// Returns `None` without at least a name and a value.
+    pub fn new<I, S>(values: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let values: Vec<String> = values.into_iter().map(Into::into).collect();
+        (values.len() >= 2).then_some(ListItem { values })
+    }
+
+    fn pair(name: &str, value: String) -> Self {
+        ListItem {
+            values: vec![name.to_string(), value],
+        }
+    }
+
+    pub fn pubkey(pubkey: &[u8; 32]) -> Self {
+        ListItem::pair("p", hex_encode(pubkey))
+    }
+
+    pub fn event(id: &[u8; 32]) -> Self {
+        ListItem::pair("e", hex_encode(id))
+    }
+
+    pub fn address(address: &NoteAddress) -> Self {
+        ListItem::pair("a", address.to_string())
+    }
+
+    pub fn hashtag(hashtag: &str) -> Self {
+        ListItem::pair("t", hashtag.to_string())
+    }
+
+    pub fn word(word: &str) -> Self {
+        ListItem::pair("word", word.to_string())
+    }
+
+    pub fn relay(url: &str) -> Self {
+        ListItem::pair("relay", url.to_string())
+    }
+
+    fn from_tag(tag: &Tag) -> Option<Self> {
+        let mut values = Vec::with_capacity(tag.count() as usize);
+        for i in 0..tag.count() {
+            let value = tag.get(i)?;
+            match value.id() {
+                Some(id) => values.push(hex_encode(id)),
+                None => values.push(value.str()?.to_owned()),
+            }
+        }
+
+        (values.len() >= 2).then_some(ListItem { values })
+    }
+
+    /// The tag name, eg. `p`
+    pub fn name(&self) -> &str {
+        &self.values[0]
+    }
+
+    /// The first value after the name, eg. the pubkey of a `p` item
+    pub fn value(&self) -> &str {
+        &self.values[1]
+    }
+
+    /// The value as an id, for `p` and `e` items
+    pub fn id(&self) -> Option<[u8; 32]> {
+        hex_decode_32(self.value())
+    }
+
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
+
+    /// Items are the same entry if their name and value match, even if
+    /// their relay hints differ
+    fn same_entry(&self, other: &ListItem) -> bool {
+        self.name() == other.name() && self.value() == other.value()
+    }
+
+    fn push_tag<'a>(&self, builder: NoteBuilder<'a>) -> NoteBuilder<'a> {
+        let mut builder = builder.start_tag();
+        for (i, value) in self.values.iter().enumerate() {
+            builder = match hex_decode_32(value) {
+                Some(id) if i > 0 => builder.tag_id(&id),
+                _ => builder.tag_str(value),
+            };
+        }
+        builder
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, value) in self.values.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json_string(&mut json, value);
+        }
+        json.push(']');
+        json
+    }
+}
+
+/// A NIP-51 list or set, like bookmarks (10003), follow sets (30000),
+/// relay sets (30002) or interest sets (30015). Items can be public tags,
+/// or private ones encrypted in the content to the list's author.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct List {
+    kind: u32,
+    author: [u8; 32],
+    metadata: Vec<ListItem>,
+    public: Vec<ListItem>,
+    private: Vec<ListItem>,
+    /// Whether the private items could be read. If not, they can't be
+    /// written back either.
+    private_readable: bool,
+}
+
+impl List {
+    /// A new, empty list. Returns `None` unless `kind` is a list (10000 to
+    /// 19999) or a set (30000 to 39999), and sets need an identifier.
+    pub fn new(kind: u32, author: &[u8; 32], identifier: Option<&str>) -> Option<Self> {
+        let mut list = List::empty(kind, author)?;
+        match identifier {
+            Some(d) => list.metadata.push(ListItem::pair("d", d.to_string())),
+            None if address::is_addressable_kind(kind) => return None,
+            None => {}
+        }
+        Some(list)
+    }
+
+    fn empty(kind: u32, author: &[u8; 32]) -> Option<Self> {
+        let is_list = (10000..20000).contains(&kind) || address::is_addressable_kind(kind);
+        is_list.then(|| List {
+            kind,
+            author: *author,
+            metadata: vec![],
+            public: vec![],
+            private: vec![],
+            private_readable: true,
+        })
+    }
+
+    /// Parse a list. With the author's `seckey`, private items are
+    /// decrypted too. Returns `None` if the note isn't a list or set kind.
+    pub fn from_note(note: &Note, seckey: Option<&[u8; 32]>) -> Option<Self> {
+        let mut list = List::empty(note.kind(), note.pubkey())?;
+        for tag in note.tags() {
+            let Some(item) = ListItem::from_tag(&tag) else {
+                continue;
+            };
+            if LIST_METADATA_TAGS.contains(&item.name()) {
+                list.metadata.push(item);
+            } else {
+                list.public.push(item);
+            }
+        }
+
+        if !note.content().is_empty() {
+            match seckey.and_then(|seckey| private_tags(note, seckey)) {
+                Some(private) => {
+                    for tag in private.tags() {
+                        list.private.extend(ListItem::from_tag(&tag));
+                    }
+                }
+                None => list.private_readable = false,
+            }
+        }
+
+        Some(list)
+    }
+
+    pub fn kind(&self) -> u32 {
+        self.kind
+    }
+
+    pub fn author(&self) -> &[u8; 32] {
+        &self.author
+    }
+
+    /// The `d` tag of a set
+    pub fn identifier(&self) -> Option<&str> {
+        self.metadata_value("d")
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.metadata_value("title")
+    }
+
+    fn metadata_value(&self, name: &str) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|item| item.name() == name)
+            .map(|item| item.value())
+    }
+
+    pub fn public_items(&self) -> &[ListItem] {
+        &self.public
+    }
+
+    /// Empty unless the list was parsed with its author's secret key
+    pub fn private_items(&self) -> &[ListItem] {
+        &self.private
+    }
+
+    /// Public and private items
+    pub fn items(&self) -> impl Iterator<Item = &ListItem> {
+        self.public.iter().chain(self.private.iter())
+    }
+
+    pub fn contains(&self, item: &ListItem) -> bool {
+        self.items().any(|i| i.same_entry(item))
+    }
+
+    /// Add a public item, unless it's already in the list. Returns false if
+    /// it was.
+    pub fn add(&mut self, item: ListItem) -> bool {
+        if self.contains(&item) {
+            return false;
+        }
+        self.public.push(item);
+        true
+    }
+
+    /// Add an item that only the author can see
+    pub fn add_private(&mut self, item: ListItem) -> bool {
+        if self.contains(&item) {
+            return false;
+        }
+        self.private.push(item);
+        true
+    }
+
+    /// Remove an item, public or private. Returns false if it wasn't there.
+    pub fn remove(&mut self, item: &ListItem) -> bool {
+        let before = self.public.len() + self.private.len();
+        self.public.retain(|i| !i.same_entry(item));
+        self.private.retain(|i| !i.same_entry(item));
+        before != self.public.len() + self.private.len()
+    }
+
+    /// Build and sign the list as a new note that replaces the old one.
+    /// `seckey` must be the author's. Returns `None` if it isn't, or if the
+    /// list has private items that couldn't be read, since they would be
+    /// lost.
+    pub fn sign(&self, seckey: &[u8; 32]) -> Option<Note<'static>> {
+        if !self.private_readable {
+            return None;
+        }
+
+        let content = if self.private.is_empty() {
+            String::new()
+        } else {
+            let items: Vec<String> = self.private.iter().map(ListItem::to_json).collect();
+            nip44::encrypt(seckey, &self.author, &format!("[{}]", items.join(","))).ok()?
+        };
+
+        let mut builder = NoteBuilder::new().kind(self.kind).content(&content);
+        for item in self.metadata.iter().chain(self.public.iter()) {
+            builder = item.push_tag(builder);
+        }
+
+        let note = builder.sign(seckey).build()?;
+        (note.pubkey() == &self.author).then_some(note)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::{test_util, Ndb, NoteBuilder, Transaction};
+
+    #[test]
+    fn mute_lists_work() {
+        let db = "target/testdbs/nip51_mutes";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let me = [90; 32];
+            let spammer = [91; 32];
+            let friend = [92; 32];
+
+            let note = |seckey: &[u8; 32], content: &str, tags: &[(&str, &str)]| {
+                let mut builder = NoteBuilder::new().kind(1).content(content).created_at(1);
+                for (name, value) in tags {
+                    builder = builder.start_tag().tag_str(name).tag_str(value);
+                }
+                builder.sign(seckey).build().expect("note")
+            };
+
+            let spam = note(&spammer, "buy now", &[]);
+            let thread = note(&friend, "a flamewar", &[]);
+            let reply = NoteBuilder::new()
+                .kind(1)
+                .content("i disagree")
+                .created_at(2)
+                .start_tag()
+                .tag_str("e")
+                .tag_id(thread.id())
+                .sign(&friend)
+                .build()
+                .expect("reply");
+            let hashtag = note(&friend, "politics", &[("t", "Politics")]);
+            let word = note(&friend, "Did you hear about the CRYPTO thing", &[]);
+            let fine = note(&friend, "good morning", &[]);
+            let me_pk = *note(&me, "", &[]).pubkey();
+
+            // the hashtag and word mutes are private
+            let private = r#"[["t","politics"],["word","crypto"]]"#;
+            let content = nip44::encrypt(&me, &me_pk, private).expect("encrypt");
+            let mute_list = NoteBuilder::new()
+                .kind(10000)
+                .content(&content)
+                .created_at(1)
+                .start_tag()
+                .tag_str("p")
+                .tag_id(spam.pubkey())
+                .start_tag()
+                .tag_str("e")
+                .tag_id(thread.id())
+                .sign(&me)
+                .build()
+                .expect("mute list");
+
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    spam.clone(),
+                    thread.clone(),
+                    reply.clone(),
+                    hashtag.clone(),
+                    word.clone(),
+                    fine.clone(),
+                    mute_list,
+                ],
+            );
+
+            let filter = Filter::new().kinds([1]).build();
+            let visible = |mutes: &MuteList| {
+                let txn = Transaction::new(&ndb).expect("txn");
+                let results = ndb
+                    .query(&txn, &[mutes.filter(&filter).expect("filter")], 10)
+                    .expect("query");
+                let mut ids: Vec<[u8; 32]> = results.iter().map(|r| *r.note.id()).collect();
+                ids.sort();
+                ids
+            };
+
+            // without the key, only the public entries apply
+            let public = {
+                let txn = Transaction::new(&ndb).expect("txn");
+                ndb.mute_list(&txn, &me_pk).expect("mute list")
+            };
+            let mut expected = vec![*hashtag.id(), *word.id(), *fine.id()];
+            expected.sort();
+            assert_eq!(visible(&public), expected);
+
+            assert!(ndb.add_key(&me));
+            let all = {
+                let txn = Transaction::new(&ndb).expect("txn");
+                ndb.mute_list(&txn, &me_pk).expect("mute list")
+            };
+            assert_eq!(visible(&all), vec![*fine.id()]);
+            assert!(!all.is_muted(&fine));
+
+            // mutes apply to new notes in subscriptions too
+            let muted_sub = all.filter(&filter).expect("filter");
+            let sub = ndb
+                .subscribe(std::slice::from_ref(&muted_sub))
+                .expect("sub");
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    note(&spammer, "more spam", &[]),
+                    note(&friend, "hello again", &[]),
+                ],
+            );
+            let keys = ndb.poll_for_notes(sub, 10);
+            assert_eq!(keys.len(), 1);
+
+            assert!(MuteList::new().is_empty());
+            assert!(MuteList::new().word("Crypto").is_muted(&word));
+
+            // older clients encrypt the private entries with NIP-04
+            let content = nip04::encrypt(&me, &me_pk, r#"[["t","politics"]]"#).expect("encrypt");
+            let legacy = NoteBuilder::new()
+                .kind(10000)
+                .content(&content)
+                .sign(&me)
+                .build()
+                .expect("legacy");
+            let legacy = MuteList::from_note(&legacy, Some(&me)).expect("mute list");
+            assert!(legacy.is_muted(&hashtag));
+
+            // a custom element the filter already has keeps working
+            let fine_id = *fine.id();
+            let custom = Filter::new()
+                .kinds([1])
+                .custom(move |note| note.id() != &fine_id)
+                .build();
+            let txn = Transaction::new(&ndb).expect("txn");
+            let results = ndb
+                .query(&txn, &[public.filter(&custom).expect("filter")], 10)
+                .expect("query");
+            let ids: Vec<[u8; 32]> = results.iter().map(|r| *r.note.id()).collect();
+            assert!(ids.contains(hashtag.id()) && ids.contains(word.id()));
+            assert!(!ids.contains(fine.id()) && !ids.contains(spam.id()));
+        }
+
+        test_util::cleanup_db(db);
+    }
+
+    #[test]
+    fn lists_work() {
+        let db = "target/testdbs/nip51_lists";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let me = [93; 32];
+            let friend = [94; 32];
+            let pubkey = |seckey: &[u8; 32]| {
+                *NoteBuilder::new()
+                    .kind(1)
+                    .content("")
+                    .sign(seckey)
+                    .build()
+                    .expect("note")
+                    .pubkey()
+            };
+            let (me_pk, friend_pk) = (pubkey(&me), pubkey(&friend));
+
+            let mut friends = List::new(30000, &me_pk, Some("friends")).expect("set");
+            assert!(friends.add(ListItem::pubkey(&friend_pk)));
+            assert!(!friends.add(ListItem::pubkey(&friend_pk)));
+            assert!(friends.add_private(ListItem::pubkey(&[1; 32])));
+            friends.add(ListItem::hashtag("nostr"));
+
+            let mut bookmarks = List::new(10003, &me_pk, None).expect("list");
+            bookmarks.add(ListItem::new(["r", "https://example.com/\"quoted\""]).expect("item"));
+
+            // only the author can sign their lists
+            assert!(friends.sign(&friend).is_none());
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    friends.sign(&me).expect("list"),
+                    bookmarks.sign(&me).expect("list"),
+                ],
+            );
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let public = ndb.list_set(&txn, 30000, &me_pk, "friends").expect("list");
+            assert_eq!(public.identifier(), Some("friends"));
+            assert_eq!(public.public_items().len(), 2);
+            assert_eq!(public.public_items()[0].id(), Some(friend_pk));
+            assert!(public.private_items().is_empty());
+            // the private items can't be read, so they can't be kept either
+            assert!(public.sign(&me).is_none());
+
+            let bookmarks = ndb.list(&txn, 10003, &me_pk).expect("list");
+            assert_eq!(
+                bookmarks.public_items()[0].value(),
+                "https://example.com/\"quoted\""
+            );
+            assert!(matches!(
+                ndb.list(&txn, 10003, &friend_pk),
+                Err(crate::Error::NotFound)
+            ));
+
+            assert!(ndb.add_key(&me));
+            let mut all = ndb.list_set(&txn, 30000, &me_pk, "friends").expect("list");
+            assert_eq!(all, friends);
+            assert_eq!(all.items().count(), 3);
+
+            assert!(all.remove(&ListItem::pubkey(&[1; 32])));
+            assert!(!all.remove(&ListItem::word("missing")));
+            let updated = all.sign(&me).expect("list");
+            let reparsed = List::from_note(&updated, Some(&me)).expect("list");
+            assert_eq!(reparsed, all);
+            assert!(reparsed.private_items().is_empty());
+            assert_eq!(reparsed.public_items()[1], ListItem::hashtag("nostr"));
+
+            assert!(ListItem::new(["t"]).is_none());
+            assert!(List::new(1, &me_pk, None).is_none());
+            assert!(List::new(30000, &me_pk, None).is_none());
+            let note = NoteBuilder::new().kind(1).content("").sign(&me).build();
+            assert!(List::from_note(&note.expect("note"), None).is_none());
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -0,0 +1,439 @@
+use crate::block::BlockIter;
+use crate::query::{walk, Visibility};
+use crate::{
+    bindings, Filter, InvoiceDescription, Note, NoteAddress, QueryOptions, Result, Transaction,
+};
+use std::ffi::CString;
+
+/// The kind used for zap receipts
+const ZAP_KIND: u64 = 9735;
+
+/// The kind used for zap requests
+const ZAP_REQUEST_KIND: u32 = 9734;
+
+/// A validated NIP-57 zap receipt (kind 9735)
+#[derive(Debug)]
+pub struct Zap<'a> {
+    request: Note<'static>,
+    recipient: &'a [u8; 32],
+    event: Option<&'a [u8; 32]>,
+    address: Option<NoteAddress<'a>>,
+    bolt11: &'a str,
+    amount_msats: u64,
+}
+
+impl<'a> Zap<'a> {
+    /// Parse and validate a zap receipt. Returns `None` unless:
+    ///
+    /// - the `description` tag is a zap request with a valid signature
+    /// - the `bolt11` tag is an invoice with an amount, whose description
+    ///   hash commits to that zap request
+    /// - the amount, recipient and zapped event agree with the zap request
+    ///
+    /// This does not check that the receipt was published by the
+    /// recipient's lnurl server, since that requires fetching their lnurl
+    /// pay endpoint.
+    pub fn from_note(note: &Note<'a>) -> Option<Zap<'a>> {
+        if note.kind() != ZAP_KIND as u32 {
+            return None;
+        }
+
+        let mut recipient = None;
+        let mut sender = None;
+        let mut event = None;
+        let mut address = None;
+        let mut bolt11 = None;
+        let mut invoice = None;
+        let mut request = None;
+
+        for tag in note.tags() {
+            if tag.count() < 2 {
+                continue;
+            }
+
+            match tag.get_str(0) {
+                Some("p") if recipient.is_none() => recipient = tag.get_id(1),
+                Some("P") if sender.is_none() => sender = tag.get_id(1),
+                Some("e") if event.is_none() => event = tag.get_id(1),
+                Some("a") if address.is_none() => {
+                    address = tag.get_str(1).and_then(NoteAddress::parse)
+                }
+                Some("bolt11") if bolt11.is_none() => {
+                    bolt11 = tag.get_str(1);
+                    invoice = bolt11.and_then(decode_bolt11);
+                }
+                Some("description") if request.is_none() => {
+                    // owned note tag strings only live as long as the tag,
+                    // so check the request while we have it
+                    let description = tag.get_str(1)?;
+                    request = Some((sha256(description), Note::from_json(description).ok()?));
+                }
+                _ => {}
+            }
+        }
+
+        let recipient = recipient?;
+        let bolt11 = bolt11?;
+        let invoice = invoice?;
+        let (digest, request) = request?;
+
+        if !invoice.commits_to(&digest) {
+            return None;
+        }
+
+        if request.kind() != ZAP_REQUEST_KIND || !request.verify() {
+            return None;
+        }
+
+        if sender.is_some_and(|sender| sender != request.pubkey()) {
+            return None;
+        }
+
+        let mut requested_recipient = None;
+        let mut requested_event = None;
+        for tag in request.tags() {
+            if tag.count() < 2 {
+                continue;
+            }
+
+            match tag.get_str(0) {
+                Some("p") if requested_recipient.is_none() => {
+                    requested_recipient = tag.get_id(1).copied()
+                }
+                Some("e") if requested_event.is_none() => requested_event = tag.get_id(1).copied(),
+                Some("amount") => {
+                    let amount: u64 = tag.get_str(1)?.parse().ok()?;
+                    if amount != invoice.amount_msats {
+                        return None;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if requested_recipient.as_ref() != Some(recipient) {
+            return None;
+        }
+
+        if requested_event.as_ref() != event {
+            return None;
+        }
+
+        Some(Zap {
+            request,
+            recipient,
+            event,
+            address,
+            bolt11,
+            amount_msats: invoice.amount_msats,
+        })
+    }
+
+    /// The kind 9734 zap request embedded in the receipt
+    pub fn request(&self) -> &Note<'static> {
+        &self.request
+    }
+
+    /// The pubkey that sent the zap
+    pub fn sender(&self) -> &[u8; 32] {
+        self.request.pubkey()
+    }
+
+    pub fn recipient(&self) -> &'a [u8; 32] {
+        self.recipient
+    }
+
+    /// The zapped note, if this isn't a profile zap
+    pub fn event(&self) -> Option<&'a [u8; 32]> {
+        self.event
+    }
+
+    /// The zapped addressable event, if any
+    pub fn address(&self) -> Option<&NoteAddress<'a>> {
+        self.address.as_ref()
+    }
+
+    /// The comment attached to the zap request
+    pub fn comment(&self) -> &str {
+        self.request.content()
+    }
+
+    pub fn bolt11(&self) -> &'a str {
+        self.bolt11
+    }
+
+    /// The invoice amount in millisatoshis
+    pub fn amount_msats(&self) -> u64 {
+        self.amount_msats
+    }
+}
+
+/// The sum of the valid zap receipts for a note or profile
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ZapTotal {
+    pub count: u32,
+    pub msats: u64,
+}
+
+impl ZapTotal {
+    /// Total the stored zaps for a note, going through every receipt
+    /// rather than stopping after some number of them.
+    pub fn for_note(txn: &Transaction, note_id: &[u8; 32]) -> Result<ZapTotal> {
+        let filter = Filter::new().kinds([ZAP_KIND]).event(note_id).build();
+        ZapTotal::walk(txn, &filter, |zap| zap.event() == Some(note_id))
+    }
+
+    /// Total the stored zaps sent to a profile, including zaps on their
+    /// notes.
+    pub fn for_profile(txn: &Transaction, pubkey: &[u8; 32]) -> Result<ZapTotal> {
+        let filter = Filter::new().kinds([ZAP_KIND]).pubkeys([pubkey]).build();
+        ZapTotal::walk(txn, &filter, |zap| zap.recipient() == pubkey)
+    }
+
+    fn walk(
+        txn: &Transaction,
+        filter: &Filter,
+        matches: impl Fn(&Zap) -> bool,
+    ) -> Result<ZapTotal> {
+        let mut total = ZapTotal::default();
+        let mut visibility = Visibility::new(txn, QueryOptions::new());
+
+        walk(txn, filter, |note| {
+            let Some(zap) = Zap::from_note(note) else {
+                return;
+            };
+
+            if matches(&zap) && visibility.keep(note) {
+                total.count += 1;
+                total.msats += zap.amount_msats();
+            }
+        })?;
+        visibility.finish()?;
+
+        Ok(total)
+    }
+}
+
+struct Bolt11 {
+    amount_msats: u64,
+    description_hash: [u8; 32],
+}
+
+impl Bolt11 {
+    /// Does the invoice description commit to a zap request with this
+    /// sha256 digest?
+    fn commits_to(&self, digest: &[u8; 32]) -> bool {
+        self.description_hash == *digest
+    }
+}
+
+fn sha256(data: &str) -> [u8; 32] {
+    let mut digest = [0u8; 32];
+    unsafe {
+        libsodium_sys::crypto_hash_sha256(digest.as_mut_ptr(), data.as_ptr(), data.len() as _)
+    };
+    digest
+}
+
+/// Decode an invoice with nostrdb's content parser, which is where the C
+/// bolt11 decoder is hooked up. Invoices without an amount aren't valid
+/// for zaps, so they're rejected here.
+fn decode_bolt11(bolt11: &str) -> Option<Bolt11> {
+    let content = CString::new(bolt11).ok()?;
+    let mut buf = vec![0u8; 1024 + bolt11.len() * 2];
+    let mut blocks: *mut bindings::ndb_blocks = std::ptr::null_mut();
+
+    let ok = unsafe {
+        bindings::ndb_parse_content(
+            buf.as_mut_ptr(),
+            buf.len() as ::std::os::raw::c_int,
+            content.as_ptr(),
+            bolt11.len() as ::std::os::raw::c_int,
+            &mut blocks,
+        )
+    };
+    if ok == 0 {
+        return None;
+    }
+
+    let invoice = BlockIter::new_owned(content.as_ptr(), blocks).find_map(|b| b.as_invoice())?;
+    let description_hash = match invoice.description()? {
+        InvoiceDescription::Text(text) => sha256(text),
+        InvoiceDescription::Hash(hash) => *hash,
+    };
+
+    Some(Bolt11 {
+        amount_msats: invoice.amount_msats()?,
+        description_hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sha256;
+    use crate::*;
+
+    const SENDER: [u8; 32] = [5; 32];
+    const SERVER: [u8; 32] = [6; 32];
+
+    fn zap_request(recipient: &[u8; 32], event: Option<&[u8; 32]>, msats: u64) -> String {
+        let mut builder = NoteBuilder::new()
+            .kind(9734)
+            .content("great post")
+            .start_tag()
+            .tag_str("p")
+            .tag_id(recipient)
+            .start_tag()
+            .tag_str("amount")
+            .tag_str(&msats.to_string())
+            .start_tag()
+            .tag_str("relays")
+            .tag_str("wss://relay.damus.io");
+
+        if let Some(event) = event {
+            builder = builder.start_tag().tag_str("e").tag_id(event);
+        }
+
+        builder
+            .sign(&SENDER)
+            .build()
+            .expect("zap request")
+            .json()
+            .expect("json")
+    }
+
+    fn zap_receipt(
+        recipient: &[u8; 32],
+        event: Option<&[u8; 32]>,
+        request: &str,
+        bolt11: &str,
+        created_at: u64,
+    ) -> Note<'static> {
+        let mut builder = NoteBuilder::new()
+            .kind(9735)
+            .content("")
+            .created_at(created_at)
+            .start_tag()
+            .tag_str("p")
+            .tag_id(recipient);
+
+        if let Some(event) = event {
+            builder = builder.start_tag().tag_str("e").tag_id(event);
+        }
+
+        builder
+            .start_tag()
+            .tag_str("bolt11")
+            .tag_str(bolt11)
+            .start_tag()
+            .tag_str("description")
+            .tag_str(request)
+            .sign(&SERVER)
+            .build()
+            .expect("zap receipt")
+    }
+
+    #[test]
+    fn zap_receipt_validates() {
+        let db = "target/testdbs/zap_receipt_validates";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let recipient = [7; 32];
+            let event = [8; 32];
+
+            let request = zap_request(&recipient, Some(&event), 21_000);
+            let bolt11 = test_util::bolt11_invoice(21_000, &[1; 32], &sha256(&request));
+            let valid = zap_receipt(&recipient, Some(&event), &request, &bolt11, 1);
+
+            // invoice doesn't commit to the request
+            let other = test_util::bolt11_invoice(21_000, &[2; 32], &[0; 32]);
+            let wrong_hash = zap_receipt(&recipient, Some(&event), &request, &other, 2);
+
+            // invoice amount doesn't match the requested amount
+            let other = test_util::bolt11_invoice(1_000, &[3; 32], &sha256(&request));
+            let wrong_amount = zap_receipt(&recipient, Some(&event), &request, &other, 3);
+
+            // receipt points at a different recipient than the request
+            let wrong_recipient = zap_receipt(&[9; 32], Some(&event), &request, &bolt11, 4);
+
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    valid.clone(),
+                    wrong_hash.clone(),
+                    wrong_amount.clone(),
+                    wrong_recipient.clone(),
+                ],
+            );
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let note = ndb.get_note_by_id(&txn, valid.id()).expect("note");
+            let zap = Zap::from_note(&note).expect("zap");
+            assert_eq!(zap.amount_msats(), 21_000);
+            assert_eq!(zap.recipient(), &recipient);
+            assert_eq!(zap.event(), Some(&event));
+            assert_eq!(zap.sender(), zap.request().pubkey());
+            assert_eq!(zap.comment(), "great post");
+            assert_eq!(zap.bolt11(), bolt11);
+
+            for invalid in [wrong_hash, wrong_amount, wrong_recipient] {
+                let note = ndb.get_note_by_id(&txn, invalid.id()).expect("note");
+                assert!(Zap::from_note(&note).is_none());
+            }
+        }
+
+        test_util::cleanup_db(db);
+    }
+
+    #[test]
+    fn zap_totals_work() {
+        let db = "target/testdbs/zap_totals";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let recipient = [7; 32];
+            let event = [8; 32];
+
+            let request1 = zap_request(&recipient, Some(&event), 21_000);
+            let bolt11 = test_util::bolt11_invoice(21_000, &[1; 32], &sha256(&request1));
+            let zap1 = zap_receipt(&recipient, Some(&event), &request1, &bolt11, 1);
+
+            let request2 = zap_request(&recipient, Some(&event), 1_000_000);
+            let bolt11 = test_util::bolt11_invoice(1_000_000, &[2; 32], &sha256(&request2));
+            let zap2 = zap_receipt(&recipient, Some(&event), &request2, &bolt11, 2);
+
+            let request3 = zap_request(&recipient, None, 5_000);
+            let bolt11 = test_util::bolt11_invoice(5_000, &[3; 32], &sha256(&request3));
+            let profile_zap = zap_receipt(&recipient, None, &request3, &bolt11, 3);
+
+            let bolt11 = test_util::bolt11_invoice(5_000, &[4; 32], &[0; 32]);
+            let invalid = zap_receipt(&recipient, Some(&event), &request1, &bolt11, 4);
+
+            test_util::ingest_notes(&ndb, &[zap1, zap2, profile_zap, invalid]);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let total = ZapTotal::for_note(&txn, &event).expect("note total");
+            assert_eq!(
+                total,
+                ZapTotal {
+                    count: 2,
+                    msats: 1_021_000
+                }
+            );
+
+            let total = ZapTotal::for_profile(&txn, &recipient).expect("profile total");
+            assert_eq!(
+                total,
+                ZapTotal {
+                    count: 3,
+                    msats: 1_026_000
+                }
+            );
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -0,0 +1,234 @@
+//! NIP-59 gift wraps: a rumor (an unsigned note) is sealed by its author in
+//! a kind 13 note, which is then wrapped in a kind 1059 note signed by a
+//! throwaway key, so relays only see who it's for.
+
+use crate::export::json_string;
+use crate::keypair::random_bytes;
+use crate::util::address::hex_encode;
+use crate::{bindings, nip44, Error, Keypair, Note, NoteBuilder, Result};
+use std::fmt::Write;
+use std::os::raw::c_int;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) const SEAL_KIND: u32 = 13;
+pub(crate) const GIFT_WRAP_KIND: u32 = 1059;
+
+/// Seals and wraps are backdated by up to this long, so their timestamps
+/// don't give away when the rumor was sent
+const MAX_BACKDATE_SECS: u64 = 2 * 24 * 60 * 60;
+
+/// A sealed and wrapped rumor, ready to send to its recipient
+#[derive(Debug, Clone)]
+pub struct GiftWrap {
+    seal: Note<'static>,
+    wrap: Note<'static>,
+}
+
+impl GiftWrap {
+    /// Seal `rumor` from `sender` and wrap it for `recipient_pubkey`. The
+    /// rumor is sent unsigned, with `sender` as its pubkey, whatever its
+    /// pubkey and signature were.
+    pub fn seal_and_wrap(
+        rumor: &Note,
+        sender: &Keypair,
+        recipient_pubkey: &[u8; 32],
+    ) -> Result<Self> {
+        let rumor = rumor_json(rumor, sender)?;
+        let seal = NoteBuilder::new()
+            .kind(SEAL_KIND)
+            .content(&nip44::encrypt(
+                sender.secret_key(),
+                recipient_pubkey,
+                &rumor,
+            )?)
+            .created_at(random_timestamp())
+            .sign(sender.secret_key())
+            .build()
+            .ok_or(Error::DecodeError)?;
+
+        let ephemeral = Keypair::generate();
+        let wrap = NoteBuilder::new()
+            .kind(GIFT_WRAP_KIND)
+            .content(&nip44::encrypt(
+                ephemeral.secret_key(),
+                recipient_pubkey,
+                &seal.json()?,
+            )?)
+            .created_at(random_timestamp())
+            .start_tag()
+            .tag_str("p")
+            .tag_id(recipient_pubkey)
+            .sign(ephemeral.secret_key())
+            .build()
+            .ok_or(Error::DecodeError)?;
+
+        Ok(GiftWrap { seal, wrap })
+    }
+
+    /// The kind 13 seal, signed by the sender
+    pub fn seal(&self) -> &Note<'static> {
+        &self.seal
+    }
+
+    /// The kind 1059 gift wrap to publish
+    pub fn wrap(&self) -> &Note<'static> {
+        &self.wrap
+    }
+
+    pub fn into_wrap(self) -> Note<'static> {
+        self.wrap
+    }
+}
+
+/// The rumor as JSON without a signature, with `sender` as its pubkey. The
+/// id is computed by nostrdb from an unsigned copy, so it matches what the
+/// recipient computes.
+fn rumor_json(rumor: &Note, sender: &Keypair) -> Result<String> {
+    let mut builder = NoteBuilder::new()
+        .kind(rumor.kind())
+        .content(rumor.content())
+        .created_at(rumor.created_at())
+        .pubkey(sender.pubkey());
+    let mut tags = String::from("[");
+    for (i, tag) in rumor.tags().iter().enumerate() {
+        if i > 0 {
+            tags.push(',');
+        }
+        tags.push('[');
+        builder = builder.start_tag();
+        for j in 0..tag.count() {
+            if j > 0 {
+                tags.push(',');
+            }
+            let value = tag.get(j);
+            match value.as_ref().and_then(|v| v.id()) {
+                Some(id) => {
+                    builder = builder.tag_id(id);
+                    let _ = write!(tags, "\"{}\"", hex_encode(id));
+                }
+                None => {
+                    let s = value.and_then(|v| v.str()).unwrap_or("");
+                    builder = builder.tag_str(s);
+                    json_string(&mut tags, s);
+                }
+            }
+        }
+        tags.push(']');
+    }
+    tags.push(']');
+
+    let unsigned = builder.build().ok_or(Error::DecodeError)?;
+    let mut id = [0u8; 32];
+    let mut buf = vec![0u8; (tags.len() + rumor.content().len()) * 6 + 1024];
+    let ok = unsafe {
+        bindings::ndb_calculate_id(
+            unsigned.as_ptr(),
+            buf.as_mut_ptr(),
+            buf.len() as c_int,
+            id.as_mut_ptr(),
+        )
+    };
+    if ok == 0 {
+        return Err(Error::BufferOverflow);
+    }
+
+    let mut content = String::new();
+    json_string(&mut content, rumor.content());
+    Ok(format!(
+        r#"{{"id":"{}","pubkey":"{}","created_at":{},"kind":{},"tags":{tags},"content":{content}}}"#,
+        hex_encode(&id),
+        hex_encode(sender.pubkey()),
+        rumor.created_at(),
+        rumor.kind()
+    ))
+}
+
+/// The pubkey a gift wrap is addressed to, from its `p` tag
+pub(crate) fn recipient(wrap: &Note) -> Option<[u8; 32]> {
+    wrap.tags()
+        .iter()
+        .find(|tag| tag.count() >= 2 && tag.get_str(0) == Some("p"))
+        .and_then(|tag| tag.get_id(1).copied())
+}
+
+/// Now, minus a random amount of up to two days
+fn random_timestamp() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut random = [0u8; 8];
+    random_bytes(&mut random);
+    now.saturating_sub(u64::from_le_bytes(random) % MAX_BACKDATE_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::{test_util, Filter, Ndb, Transaction};
+
+    #[test]
+    fn gift_wraps_unwrap() {
+        let db = "target/testdbs/nip59_giftwrap";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let alice = Keypair::from_secret(&[5; 32]).expect("alice");
+            let bob = Keypair::from_secret(&[6; 32]).expect("bob");
+            assert!(Keypair::from_secret(&[0; 32]).is_none());
+
+            let rumor = NoteBuilder::new()
+                .kind(14)
+                .content("hi bob, \"quoted\"")
+                .created_at(1_700_000_000)
+                .start_tag()
+                .tag_str("p")
+                .tag_id(bob.pubkey())
+                .build()
+                .expect("rumor");
+
+            let gift = GiftWrap::seal_and_wrap(&rumor, &alice, bob.pubkey()).expect("wrap");
+            assert_eq!(gift.seal().kind(), SEAL_KIND);
+            assert_eq!(gift.seal().pubkey(), alice.pubkey());
+            assert_eq!(gift.wrap().kind(), GIFT_WRAP_KIND);
+            assert_ne!(gift.wrap().pubkey(), alice.pubkey());
+            assert!(gift.wrap().created_at() <= gift.seal().created_at() + MAX_BACKDATE_SECS);
+
+            // only bob can open the seal
+            let seal_json = nip44::decrypt(
+                bob.secret_key(),
+                gift.wrap().pubkey(),
+                gift.wrap().content(),
+            )
+            .expect("unwrap");
+            assert!(seal_json.contains(r#""kind":13"#));
+            let rumor_json =
+                nip44::decrypt(bob.secret_key(), alice.pubkey(), gift.seal().content())
+                    .expect("unseal");
+            assert!(!rumor_json.contains("sig"));
+
+            assert!(ndb.add_key(bob.secret_key()));
+            let wrap_id = *gift.wrap().id();
+            test_util::ingest_notes(&ndb, &[gift.into_wrap()]);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let filter = Filter::new().kinds([14]).build();
+            let results = ndb.query(&txn, &[filter], 10).expect("query");
+            assert_eq!(results.len(), 1);
+            let received = &results[0].note;
+            assert!(received.is_rumor());
+            assert_eq!(received.pubkey(), alice.pubkey());
+            assert_eq!(received.content(), "hi bob, \"quoted\"");
+            assert_eq!(received.created_at(), 1_700_000_000);
+            assert_eq!(received.rumor_receiver_pubkey(), Some(bob.pubkey()));
+            assert_eq!(received.rumor_giftwrap_id(), Some(&wrap_id));
+            // nostrdb recomputes the id, which matches the one we sent
+            let sent_id = format!(r#""id":"{}""#, hex_encode(received.id()));
+            assert!(rumor_json.starts_with(&format!("{{{sent_id}")));
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
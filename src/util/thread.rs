@@ -0,0 +1,359 @@
+use crate::{Error, Filter, Ndb, Note, NoteReply, Result, Transaction};
+use std::collections::HashMap;
+
+/// A note inside of a [`Thread`], along with its links in the reply tree
+#[derive(Debug)]
+pub struct ThreadNode<'a> {
+    note: Note<'a>,
+    parent: Option<[u8; 32]>,
+    children: Vec<usize>,
+}
+
+impl<'a> ThreadNode<'a> {
+    pub fn note(&self) -> &Note<'a> {
+        &self.note
+    }
+
+    pub fn id(&self) -> &'a [u8; 32] {
+        self.note.id()
+    }
+
+    /// The id of the note this is replying to. This may point at a note
+    /// that isn't in the database, see [`Thread::missing`].
+    pub fn parent_id(&self) -> Option<&[u8; 32]> {
+        self.parent.as_ref()
+    }
+}
+
+/// A conversation assembled from NIP-10 `e` tags. Contains the root note,
+/// every stored reply to it and the parent/child links between them. All
+/// nodes and children are ordered by `created_at`.
+#[derive(Debug)]
+pub struct Thread<'a> {
+    root_id: [u8; 32],
+    focus: usize,
+    nodes: Vec<ThreadNode<'a>>,
+    index: HashMap<[u8; 32], usize>,
+    missing: Vec<[u8; 32]>,
+}
+
+impl<'a> Thread<'a> {
+    /// Build the thread that `note_id` belongs to. `max_replies` limits how
+    /// many replies are pulled from the database, the same way
+    /// `max_results` does for [`Ndb::query`].
+    pub fn new(
+        ndb: &Ndb,
+        txn: &'a Transaction,
+        note_id: &[u8; 32],
+        max_replies: i32,
+    ) -> Result<Thread<'a>> {
+        let focus = ndb.get_note_by_id(txn, note_id)?;
+        let root_id = NoteReply::new(focus.tags())
+            .root()
+            .map(|r| *r.id)
+            .unwrap_or(*note_id);
+
+        let mut thread = Thread {
+            root_id,
+            focus: 0,
+            nodes: vec![],
+            index: HashMap::new(),
+            missing: vec![],
+        };
+
+        thread.push(focus);
+
+        let filter = if root_id == *note_id {
+            Filter::new().kinds([1]).event(&root_id).build()
+        } else {
+            if let Some(root) = get_note_opt(ndb, txn, &root_id)? {
+                thread.push(root);
+            }
+            Filter::new().kinds([1]).events([&root_id, note_id]).build()
+        };
+
+        for res in ndb.query(txn, &[filter], max_replies)? {
+            // notes that only mention the thread aren't part of it
+            if NoteReply::new(res.note.tags()).reply().is_some() {
+                thread.push(res.note);
+            }
+        }
+
+        // pull in any ancestors that didn't tag the root, and record the
+        // ones we don't have so they can be fetched
+        let mut i = 0;
+        while i < thread.nodes.len() {
+            if let Some(parent) = thread.nodes[i].parent {
+                if !thread.index.contains_key(&parent) && !thread.missing.contains(&parent) {
+                    match get_note_opt(ndb, txn, &parent)? {
+                        Some(note) => thread.push(note),
+                        None => thread.missing.push(parent),
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        thread.link(note_id);
+        Ok(thread)
+    }
+
+    fn push(&mut self, note: Note<'a>) {
+        let id = *note.id();
+        if self.index.contains_key(&id) {
+            return;
+        }
+
+        let parent = if id == self.root_id {
+            None
+        } else {
+            NoteReply::new(note.tags()).reply().map(|r| *r.id)
+        };
+
+        self.index.insert(id, self.nodes.len());
+        self.nodes.push(ThreadNode {
+            note,
+            parent,
+            children: vec![],
+        });
+    }
+
+    fn link(&mut self, focus_id: &[u8; 32]) {
+        self.nodes.sort_by(|a, b| {
+            a.note
+                .created_at()
+                .cmp(&b.note.created_at())
+                .then_with(|| a.note.id().cmp(b.note.id()))
+        });
+
+        self.index.clear();
+        for (i, node) in self.nodes.iter().enumerate() {
+            self.index.insert(*node.note.id(), i);
+        }
+
+        for i in 0..self.nodes.len() {
+            let Some(parent) = self.nodes[i].parent else {
+                continue;
+            };
+
+            if let Some(&p) = self.index.get(&parent) {
+                self.nodes[p].children.push(i);
+            }
+        }
+
+        self.focus = self.index[focus_id];
+    }
+
+    /// The id of the thread root. The root note itself may be missing.
+    pub fn root_id(&self) -> &[u8; 32] {
+        &self.root_id
+    }
+
+    pub fn root(&self) -> Option<&ThreadNode<'a>> {
+        self.get(&self.root_id)
+    }
+
+    /// The note this thread was built from
+    pub fn focus(&self) -> &ThreadNode<'a> {
+        &self.nodes[self.focus]
+    }
+
+    pub fn get(&self, id: &[u8; 32]) -> Option<&ThreadNode<'a>> {
+        self.index.get(id).map(|&i| &self.nodes[i])
+    }
+
+    pub fn parent(&self, node: &ThreadNode<'a>) -> Option<&ThreadNode<'a>> {
+        node.parent.as_ref().and_then(|id| self.get(id))
+    }
+
+    pub fn children<'t>(
+        &'t self,
+        node: &'t ThreadNode<'a>,
+    ) -> impl Iterator<Item = &'t ThreadNode<'a>> + 't {
+        node.children.iter().map(move |&i| &self.nodes[i])
+    }
+
+    /// The stored chain of parents of a node, starting from the topmost
+    /// one we have.
+    pub fn ancestors(&self, node: &ThreadNode<'a>) -> Vec<&ThreadNode<'a>> {
+        let mut ancestors = vec![];
+        let mut current = self.parent(node);
+        while let Some(parent) = current {
+            // guard against reply cycles from malformed tags
+            if ancestors.len() >= self.nodes.len() {
+                break;
+            }
+            ancestors.push(parent);
+            current = self.parent(parent);
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    /// Every note in the thread, ordered by `created_at`
+    pub fn iter(&self) -> impl Iterator<Item = &ThreadNode<'a>> {
+        self.nodes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Ids of referenced ancestors that aren't in the database, including
+    /// the root if we don't have it. Fetch these from relays to complete
+    /// the thread.
+    pub fn missing(&self) -> &[[u8; 32]] {
+        &self.missing
+    }
+}
+
+fn get_note_opt<'a>(ndb: &Ndb, txn: &'a Transaction, id: &[u8; 32]) -> Result<Option<Note<'a>>> {
+    match ndb.get_note_by_id(txn, id) {
+        Ok(note) => Ok(Some(note)),
+        Err(Error::NotFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const SECKEY_A: [u8; 32] = [1; 32];
+    const SECKEY_B: [u8; 32] = [2; 32];
+
+    fn reply<'a>(
+        seckey: &'a [u8; 32],
+        created_at: u64,
+        root: &[u8; 32],
+        parent: Option<&[u8; 32]>,
+    ) -> NoteBuilder<'a> {
+        let mut builder = NoteBuilder::new()
+            .kind(1)
+            .content("reply")
+            .created_at(created_at)
+            .start_tag()
+            .tag_str("e")
+            .tag_id(root)
+            .tag_str("")
+            .tag_str("root");
+
+        if let Some(parent) = parent {
+            builder = builder
+                .start_tag()
+                .tag_str("e")
+                .tag_id(parent)
+                .tag_str("")
+                .tag_str("reply");
+        }
+
+        builder.sign(seckey)
+    }
+
+    #[test]
+    fn thread_assembles_tree() {
+        let db = "target/testdbs/thread_assembles_tree";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let root = NoteBuilder::new()
+                .kind(1)
+                .content("root")
+                .created_at(100)
+                .sign(&SECKEY_A)
+                .build()
+                .expect("root");
+            let missing_parent = [0xab; 32];
+            let reply1 = reply(&SECKEY_B, 200, root.id(), None)
+                .build()
+                .expect("reply1");
+            let reply2 = reply(&SECKEY_A, 300, root.id(), Some(reply1.id()))
+                .build()
+                .expect("reply2");
+            let orphan = reply(&SECKEY_B, 150, root.id(), Some(&missing_parent))
+                .build()
+                .expect("orphan");
+            let late = reply(&SECKEY_B, 400, root.id(), None)
+                .build()
+                .expect("late");
+
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    root.clone(),
+                    reply1.clone(),
+                    reply2.clone(),
+                    orphan.clone(),
+                    late.clone(),
+                ],
+            );
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let thread = Thread::new(&ndb, &txn, reply2.id(), 100).expect("thread");
+
+            assert_eq!(thread.len(), 5);
+            assert_eq!(thread.root_id(), root.id());
+            assert_eq!(thread.focus().id(), reply2.id());
+            assert_eq!(thread.missing(), &[missing_parent]);
+
+            let order: Vec<&[u8; 32]> = thread.iter().map(|n| n.id()).collect();
+            assert_eq!(
+                order,
+                vec![root.id(), orphan.id(), reply1.id(), reply2.id(), late.id()]
+            );
+
+            let root_node = thread.root().expect("root node");
+            let children: Vec<&[u8; 32]> = thread.children(root_node).map(|n| n.id()).collect();
+            assert_eq!(children, vec![reply1.id(), late.id()]);
+
+            let ancestors: Vec<&[u8; 32]> = thread
+                .ancestors(thread.focus())
+                .into_iter()
+                .map(|n| n.id())
+                .collect();
+            assert_eq!(ancestors, vec![root.id(), reply1.id()]);
+
+            let orphan_node = thread.get(orphan.id()).expect("orphan node");
+            assert_eq!(orphan_node.parent_id(), Some(&missing_parent));
+            assert!(thread.parent(orphan_node).is_none());
+        }
+
+        test_util::cleanup_db(db);
+    }
+
+    #[test]
+    fn thread_reports_missing_root() {
+        let db = "target/testdbs/thread_reports_missing_root";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let root_id = [0xcd; 32];
+            let reply1 = reply(&SECKEY_A, 200, &root_id, None)
+                .build()
+                .expect("reply1");
+            let reply2 = reply(&SECKEY_B, 300, &root_id, Some(reply1.id()))
+                .build()
+                .expect("reply2");
+
+            test_util::ingest_notes(&ndb, &[reply1.clone(), reply2.clone()]);
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let thread = Thread::new(&ndb, &txn, reply1.id(), 100).expect("thread");
+
+            assert!(thread.root().is_none());
+            assert_eq!(thread.missing(), &[root_id]);
+            assert_eq!(thread.len(), 2);
+            let children: Vec<&[u8; 32]> =
+                thread.children(thread.focus()).map(|n| n.id()).collect();
+            assert_eq!(children, vec![reply2.id()]);
+        }
+
+        test_util::cleanup_db(db);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{Ndb, Result, Transaction};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+/// How much a pubkey is trusted from some root pubkey, from
+/// [Ndb::wot_scores]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct WotScore {
+    pub pubkey: [u8; 32],
+    /// How many trusted pubkeys follow this one
+    pub followers: u32,
+    /// How many follows away from the root this pubkey is
+    pub distance: u32,
+}
+
+/// The pubkeys followed by `pubkey`
+fn followed_by(ndb: &Ndb, txn: &Transaction, pubkey: &[u8; 32]) -> Result<Vec<[u8; 32]>> {
+    Ok(ndb
+        .follows(txn, pubkey)?
+        .iter()
+        .map(|follow| *follow.followed)
+        .collect())
+}
+
+/// Follow contact lists breadth first from `from` until reaching `to`
+pub(crate) fn distance(
+    ndb: &Ndb,
+    txn: &Transaction,
+    from: &[u8; 32],
+    to: &[u8; 32],
+    max_hops: u32,
+) -> Result<Option<u32>> {
+    if from == to {
+        return Ok(Some(0));
+    }
+
+    let mut visited = HashSet::from([*from]);
+    let mut frontier = vec![*from];
+
+    for hops in 1..=max_hops {
+        let mut next = vec![];
+        for pubkey in &frontier {
+            for followed in followed_by(ndb, txn, pubkey)? {
+                if followed == *to {
+                    return Ok(Some(hops));
+                }
+                if visited.insert(followed) {
+                    next.push(followed);
+                }
+            }
+        }
+
+        if next.is_empty() {
+            break;
+        }
+        frontier = next;
+    }
+
+    Ok(None)
+}
+
+/// Trust everyone within `depth` follows of `root`, and score every pubkey
+/// they follow by how many of them follow it
+pub(crate) fn scores(
+    ndb: &Ndb,
+    txn: &Transaction,
+    root: &[u8; 32],
+    depth: u32,
+) -> Result<Vec<WotScore>> {
+    let mut distances = HashMap::from([(*root, 0)]);
+    let mut followers: HashMap<[u8; 32], u32> = HashMap::new();
+    let mut frontier = vec![*root];
+
+    for hops in 1..=depth + 1 {
+        let mut next = vec![];
+        for follower in &frontier {
+            for followed in followed_by(ndb, txn, follower)? {
+                // the root's follows aren't scored by the root itself
+                if follower != root {
+                    *followers.entry(followed).or_default() += 1;
+                }
+                if let Entry::Vacant(entry) = distances.entry(followed) {
+                    entry.insert(hops);
+                    next.push(followed);
+                }
+            }
+        }
+
+        // pubkeys beyond the trusted depth are scored, but not expanded
+        if hops > depth {
+            break;
+        }
+        frontier = next;
+    }
+
+    let mut scores: Vec<WotScore> = distances
+        .into_iter()
+        .filter(|(pubkey, distance)| *distance > 0 && pubkey != root)
+        .map(|(pubkey, distance)| WotScore {
+            pubkey,
+            followers: followers.get(&pubkey).copied().unwrap_or(0),
+            distance,
+        })
+        .collect();
+
+    scores.sort_by(|a, b| {
+        b.followers
+            .cmp(&a.followers)
+            .then_with(|| a.distance.cmp(&b.distance))
+            .then_with(|| a.pubkey.cmp(&b.pubkey))
+    });
+
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::{test_util, NoteBuilder};
+
+    #[test]
+    fn wot_works() {
+        let db = "target/testdbs/wot";
+        test_util::cleanup_db(db);
+
+        {
+            let ndb = Ndb::new(db, &Config::new()).expect("ndb");
+            let seckeys: Vec<[u8; 32]> = (0..6).map(|i| [80 + i; 32]).collect();
+            let pubkeys: Vec<[u8; 32]> = seckeys
+                .iter()
+                .map(|seckey| {
+                    *NoteBuilder::new()
+                        .kind(1)
+                        .content("")
+                        .sign(seckey)
+                        .build()
+                        .expect("note")
+                        .pubkey()
+                })
+                .collect();
+
+            let contacts = |i: usize, follows: &[usize]| {
+                let mut builder = NoteBuilder::new().kind(3).content("").created_at(1);
+                for &f in follows {
+                    builder = builder.start_tag().tag_str("p").tag_id(&pubkeys[f]);
+                }
+                builder.sign(&seckeys[i]).build().expect("contacts")
+            };
+
+            // 0 follows 1 and 2, who both follow 3. 2 also follows 4, and 4
+            // follows 5
+            test_util::ingest_notes(
+                &ndb,
+                &[
+                    contacts(0, &[1, 2]),
+                    contacts(1, &[3]),
+                    contacts(2, &[3, 4]),
+                    contacts(4, &[5]),
+                ],
+            );
+
+            let txn = Transaction::new(&ndb).expect("txn");
+            let distance = |to: usize, max_hops: u32| {
+                ndb.wot_distance(&txn, &pubkeys[0], &pubkeys[to], max_hops)
+                    .expect("distance")
+            };
+            assert_eq!(distance(0, 3), Some(0));
+            assert_eq!(distance(2, 3), Some(1));
+            assert_eq!(distance(3, 3), Some(2));
+            assert_eq!(distance(5, 3), Some(3));
+            assert_eq!(distance(5, 2), None);
+
+            let scores = ndb.wot_scores(&txn, &pubkeys[0], 1).expect("scores");
+            assert_eq!(
+                scores[0],
+                WotScore {
+                    pubkey: pubkeys[3],
+                    followers: 2,
+                    distance: 2
+                }
+            );
+            assert_eq!(scores[1].pubkey, pubkeys[4]);
+            assert_eq!(scores[1].followers, 1);
+            // 5 is only followed by someone two hops away
+            assert!(!scores.iter().any(|s| s.pubkey == pubkeys[5]));
+            assert!(scores
+                .iter()
+                .any(|s| s.pubkey == pubkeys[1] && s.followers == 0));
+
+            let scores = ndb.wot_scores(&txn, &pubkeys[0], 2).expect("scores");
+            assert!(scores
+                .iter()
+                .any(|s| s.pubkey == pubkeys[5] && s.followers == 1 && s.distance == 3));
+        }
+
+        test_util::cleanup_db(db);
+    }
+}